@@ -1,10 +1,9 @@
 #! /bin/sh
 #
 # This script builds an minimal example RPKI repository using the mkrpki
-# binary and validates it using Routinator
+# binary and validates it using mkrpki validate
 
 MKRPKI=../target/debug/mkrpki
-ROUTINATOR=routinator
 
 echo "--- Preparing repository"
 
@@ -54,7 +53,7 @@ $MKRPKI cer \
 	--ca-issuer rsync://rpki.example.com/repo/ta.cer \
 	--ca-repository rsync://rpki.example.com/repo/isp/ \
 	--rpki-manifest rsync://rpki.example.com/repo/isp/isp.mft \
-	--v4 192.0.2.0/124 \
+	--v4 192.0.2.0/24 \
 	--as AS64494 \
 	--output test-repo/repository/rpki.example.com/repo/isp.cer
 
@@ -90,7 +89,7 @@ $MKRPKI roa \
 	--ca-issuer rsync://rpki.example.com/repo/isp.cer \
 	--signed-object rsync://rpki.example.com/repo/isp/isp.roa \
 	--asn AS64494 \
-	--prefixes 192.0.2.0/24-2 \
+	--prefixes 192.0.2.0/24 \
 	--output test-repo/repository/rpki.example.com/repo/isp/isp.roa
 
 $MKRPKI mft \
@@ -108,4 +107,6 @@ $MKRPKI mft \
 	--output test-repo/repository/rpki.example.com/repo/isp/isp.mft
 
 echo "\n\n--- Validating repository"
-$ROUTINATOR -b test-repo vrps -n
+$MKRPKI validate \
+	--tal test-repo/tals/example.tal \
+	--repository test-repo/repository
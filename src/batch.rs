@@ -0,0 +1,399 @@
+//! Creating many ROAs at once.
+//!
+//! The input is a list of entries, each an AS number, a prefix, and an
+//! optional max length. It is given either as a JSON array of objects with
+//! the fields `asn`, `prefix`, and `maxLength` or as CSV with these three
+//! columns in this order. The entries are grouped into ROAs, either one ROA
+//! per AS number or one per AS number and prefix.
+//!
+//! The ROAs are created by running the `roa` command for each group.
+
+use std::fs;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use mkrpki::Error;
+use rpki::repository::resources::Asn;
+use rpki::repository::roa::RoaIpAddress;
+use rpki::uri;
+use serde::Deserialize;
+use structopt::StructOpt;
+use crate::clock::{Period, TimeSpec};
+use crate::issuer::{IssuerCert, resolve_uri};
+use super::{load_file, Roa, RoaPrefix};
+
+
+//------------ RoaBatch ------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct RoaBatch {
+    /// Path to the private key of the certificate issuer.
+    #[structopt(long="issuer-key")]
+    issuer_key: PathBuf,
+
+    /// Path to the certificate of the issuer to derive URIs from. Must have
+    /// the file name the certificate is published under.
+    #[structopt(long="issuer-cert")]
+    issuer_cert: Option<PathBuf>,
+
+    /// Serial number of the first ROA. Taken from the state if missing.
+    #[structopt(long="serial")]
+    serial: Option<u64>,
+
+    /// Not-before date of the ROAs. Defaults to now.
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
+
+    /// Not-after date of the ROAs.
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
+
+    /// Duration of validity of the ROAs, in days if just a number.
+    #[structopt(long="days")]
+    valid_days: Option<Period>,
+
+    /// RPKI URI of the CRL. Derived from the issuer certificate if missing.
+    #[structopt(long="crl")]
+    crl_uri: Option<uri::Rsync>,
+
+    /// CA issuer URI. Derived from the issuer certificate if missing.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: Option<uri::Rsync>,
+
+    /// URI of the directory the ROAs are published in.
+    #[structopt(long="ca-repository")]
+    ca_repository: Option<uri::Rsync>,
+
+    /// Path to the CSV or JSON file with the ROA entries.
+    #[structopt(long="input")]
+    input: PathBuf,
+
+    /// How to group entries into ROAs: 'per-asn' (default) or 'per-prefix'.
+    #[structopt(long="group", default_value="per-asn")]
+    group: Grouping,
+
+    /// Leave out prefixes not covered by the issuer certificate.
+    #[structopt(long="trim-overclaim")]
+    trim_overclaim: bool,
+
+    /// Keep prefixes not covered by the issuer certificate.
+    #[structopt(long="allow-overclaim")]
+    allow_overclaim: bool,
+
+    /// Path to the directory with the state of the issuing CA.
+    #[structopt(long="state")]
+    state: Option<PathBuf>,
+
+    /// Path to the directory to write the ROAs into.
+    #[structopt(long="output-dir")]
+    output_dir: PathBuf,
+}
+
+impl RoaBatch {
+    pub fn run(self) -> Result<(), Error> {
+        if self.serial.is_none() && self.state.is_none() {
+            return Err(Error::Usage(
+                "Either --serial or --state must be given.".into()
+            ))
+        }
+        let entries = RoaEntry::load(&self.input)?;
+        if entries.is_empty() {
+            return Err(Error::Usage(format!(
+                "No ROA entries in {}.", self.input.display()
+            )))
+        }
+        let ca_repository = resolve_uri(
+            self.ca_repository,
+            IssuerCert::load_opt(self.issuer_cert.as_deref())?.as_ref(),
+            "--ca-repository", IssuerCert::ca_repository
+        )?;
+        if let Err(err) = fs::create_dir_all(&self.output_dir) {
+            return Err(Error::Io(format!(
+                "Failed to create directory {}: {}",
+                self.output_dir.display(), err
+            )))
+        }
+
+        let mut groups = BTreeMap::<_, Vec<RoaPrefix>>::new();
+        for entry in entries {
+            let name = self.group.file_name(&entry);
+            groups.entry((entry.asn, name)).or_default().push(
+                entry.to_prefix()
+            );
+        }
+        let groups = groups.into_iter().enumerate();
+        for (index, ((asn, name), prefixes)) in groups {
+            let signed_object = ca_repository.join(name.as_bytes()).map_err(
+                |_| Error::Usage(format!("Invalid file name {}.", name))
+            )?;
+            Roa {
+                issuer_key: self.issuer_key.clone(),
+                issuer_cert: self.issuer_cert.clone(),
+                serial: self.serial.map(|serial| {
+                    serial.saturating_add(index as u64).into()
+                }),
+                not_before: self.not_before,
+                not_after: self.not_after,
+                valid_days: self.valid_days,
+                crl_uri: self.crl_uri.clone(),
+                ca_issuer: self.ca_issuer.clone(),
+                signed_object: Some(signed_object),
+                asn,
+                prefixes,
+                trim_overclaim: self.trim_overclaim,
+                allow_overclaim: self.allow_overclaim,
+                state: self.state.clone(),
+                corrupt: None,
+                output: self.output_dir.join(&name),
+            }.run()?;
+        }
+        Ok(())
+    }
+}
+
+
+//------------ Grouping ------------------------------------------------------
+
+/// How entries are grouped into ROAs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Grouping {
+    /// One ROA with all the prefixes of an AS number.
+    PerAsn,
+
+    /// One ROA for each AS number and prefix.
+    PerPrefix,
+}
+
+impl Grouping {
+    /// Returns the file name of the ROA an entry belongs to.
+    fn file_name(self, entry: &RoaEntry) -> String {
+        match self {
+            Grouping::PerAsn => format!("{}.roa", entry.asn),
+            Grouping::PerPrefix => {
+                format!(
+                    "{}-{}-{}.roa",
+                    entry.asn, entry.addr.to_string().replace(':', "_"),
+                    entry.len
+                )
+            }
+        }
+    }
+}
+
+impl FromStr for Grouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-asn" => Ok(Grouping::PerAsn),
+            "per-prefix" => Ok(Grouping::PerPrefix),
+            _ => Err(format!("invalid grouping '{}'", s))
+        }
+    }
+}
+
+
+//------------ RoaEntry ------------------------------------------------------
+
+/// A single prefix authorized for an AS number.
+#[derive(Clone, Copy, Debug)]
+pub struct RoaEntry {
+    /// The AS number.
+    asn: Asn,
+
+    /// The address of the prefix.
+    addr: IpAddr,
+
+    /// The length of the prefix.
+    len: u8,
+
+    /// The optional max length.
+    max_len: Option<u8>,
+}
+
+impl RoaEntry {
+    /// Creates an entry from its textual components.
+    fn from_strs(
+        asn: &str, prefix: &str, max_len: Option<&str>
+    ) -> Result<Self, String> {
+        let max_len = match max_len.map(str::trim) {
+            Some("") | None => None,
+            Some(max_len) => Some(u8::from_str(max_len).map_err(|_| {
+                format!("invalid max length '{}'", max_len)
+            })?)
+        };
+        Self::new(parse_asn(asn)?, prefix, max_len)
+    }
+
+    /// Creates an entry after checking the prefix and max length.
+    pub fn new(
+        asn: Asn, prefix: &str, max_len: Option<u8>
+    ) -> Result<Self, String> {
+        let (addr, len) = prefix.trim().split_once('/').and_then(|(a, l)| {
+            Some((IpAddr::from_str(a).ok()?, u8::from_str(l).ok()?))
+        }).ok_or_else(|| format!("invalid prefix '{}'", prefix))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        if len > bits {
+            return Err(format!("invalid prefix length {}", len))
+        }
+        if let Some(max_len) = max_len {
+            if max_len < len || max_len > bits {
+                return Err(format!("invalid max length {}", max_len))
+            }
+        }
+        Ok(RoaEntry { asn, addr, len, max_len })
+    }
+
+    /// Loads the entries from a file.
+    ///
+    /// Files with the extension `.json` are read as JSON, all others as
+    /// CSV.
+    pub fn load(path: &Path) -> Result<Vec<Self>, Error> {
+        let data = load_file(path)?;
+        let res = match path.extension().and_then(OsStr::to_str) {
+            Some("json") => Self::from_json(&data),
+            _ => Self::from_csv(&data),
+        };
+        res.map_err(|err| {
+            Error::Usage(format!(
+                "Invalid ROA entries {}: {}", path.display(), err
+            ))
+        })
+    }
+
+    /// Parses entries from a JSON array.
+    fn from_json(data: &[u8]) -> Result<Vec<Self>, String> {
+        let items: Vec<JsonEntry> = serde_json::from_slice(data).map_err(
+            |err| err.to_string()
+        )?;
+        items.into_iter().map(|item| {
+            let asn = match item.asn {
+                JsonAsn::Number(asn) => asn.into(),
+                JsonAsn::Text(asn) => parse_asn(&asn)?,
+            };
+            Self::new(asn, &item.prefix, item.max_length)
+        }).collect()
+    }
+
+    /// Parses entries from CSV.
+    fn from_csv(data: &[u8]) -> Result<Vec<Self>, String> {
+        csv_records(data)?.into_iter().map(|(line, fields)| {
+            Self::from_fields(&fields).map_err(|err| {
+                format!("line {}: {}", line, err)
+            })
+        }).collect()
+    }
+
+    /// Creates an entry from the fields of a CSV record.
+    ///
+    /// The fields are the AS number, the prefix, and the optional max
+    /// length. Any further fields are ignored.
+    pub fn from_fields(fields: &[&str]) -> Result<Self, String> {
+        match *fields {
+            [asn, prefix] => Self::from_strs(asn, prefix, None),
+            [asn, prefix, max_len, ..] => {
+                Self::from_strs(asn, prefix, Some(max_len))
+            }
+            _ => Err("missing prefix".into())
+        }
+    }
+
+    /// Returns the AS number.
+    pub fn asn(&self) -> Asn {
+        self.asn
+    }
+
+    /// Returns the prefix in slash notation.
+    pub fn prefix(&self) -> String {
+        format!("{}/{}", self.addr, self.len)
+    }
+
+    /// Returns whether the prefix is an IPv4 prefix.
+    pub fn is_v4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    /// Returns the prefix and max length in the format of `--prefixes`.
+    pub fn roa_prefix(&self) -> String {
+        match self.max_len {
+            Some(max_len) => format!("{}-{}", self.prefix(), max_len),
+            None => self.prefix()
+        }
+    }
+
+    /// Converts the entry into a ROA prefix.
+    fn to_prefix(self) -> RoaPrefix {
+        RoaPrefix {
+            v4: self.addr.is_ipv4(),
+            prefix: RoaIpAddress::new_addr(
+                self.addr, self.len, self.max_len
+            ),
+        }
+    }
+}
+
+
+//------------ JsonEntry -----------------------------------------------------
+
+/// An entry in a JSON input file.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonEntry {
+    /// The AS number, either as a number or a string.
+    asn: JsonAsn,
+
+    /// The prefix.
+    prefix: String,
+
+    /// The optional max length.
+    #[serde(default)]
+    max_length: Option<u8>,
+}
+
+/// An AS number in JSON, either as a number or a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonAsn {
+    Number(u32),
+    Text(String),
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Returns the records of a CSV file with their line numbers.
+///
+/// Empty lines and lines starting with `#` are ignored. If the first field
+/// of the first remaining line isn’t an AS number, the line is taken as a
+/// header and ignored, too.
+pub fn csv_records(data: &[u8]) -> Result<Vec<(usize, Vec<&str>)>, String> {
+    let data = std::str::from_utf8(data).map_err(|_| {
+        String::from("invalid UTF-8")
+    })?;
+    let mut res = Vec::new();
+    let mut header_checked = false;
+    for (idx, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        if !header_checked {
+            header_checked = true;
+            if parse_asn(fields[0]).is_err() {
+                continue
+            }
+        }
+        res.push((idx + 1, fields));
+    }
+    Ok(res)
+}
+
+/// Parses an AS number with or without the `AS` prefix.
+pub fn parse_asn(asn: &str) -> Result<Asn, String> {
+    Asn::from_str(asn.trim()).map_err(|_| {
+        format!("invalid AS number '{}'", asn)
+    })
+}
@@ -0,0 +1,626 @@
+//! Building a complete repository from a description.
+//!
+//! The description is a TOML or YAML file describing a tree of CAs starting
+//! with a trust anchor. For each CA, it lists the resources, the child CAs,
+//! and the signed objects it should publish. Everything else, i.e., keys,
+//! serial numbers, and all the URIs, is derived from the tree.
+//!
+//! The objects are created by running the same operations that are
+//! available as individual commands.
+
+use std::fmt;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use mkrpki::Error;
+use rpki::repository::x509::Serial;
+use rpki::uri;
+use serde::Deserialize;
+use structopt::StructOpt;
+use crate::clock::Period;
+use super::{
+    load_file, Aspa, Cert, Crl, Format, Gbr, Key, KeyAlgorithm, Mft, Roa, Ta
+};
+
+
+//------------ Build ---------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Build {
+    /// Path to the TOML or YAML repository description.
+    #[structopt(long="spec")]
+    spec: PathBuf,
+
+    /// Path to the directory to write the repository into.
+    #[structopt(long="output")]
+    output: PathBuf,
+}
+
+impl Build {
+    pub fn run(self) -> Result<(), Error> {
+        Spec::load(&self.spec)?.build(self.output)
+    }
+}
+
+
+//------------ Builder -------------------------------------------------------
+
+/// The state kept while building a repository.
+struct Builder {
+    /// The output directory.
+    output: PathBuf,
+
+    /// The number of days all objects are valid.
+    days: i64,
+
+    /// The RRDP notification URI to include in CA certificates.
+    rrdp_notify: Option<uri::Https>,
+
+    /// The last serial number used.
+    serial: u64,
+}
+
+impl Builder {
+    /// Returns the next serial number.
+    ///
+    /// Serial numbers are unique across the whole repository which makes
+    /// sure they are unique for each issuer, too.
+    fn next_serial(&mut self) -> Serial {
+        self.serial += 1;
+        self.serial.into()
+    }
+
+    /// Returns the path of the private key of a CA.
+    fn private_key(&self, ca: &CaSpec) -> PathBuf {
+        self.output.join("keys").join(format!("{}.key", ca.name))
+    }
+
+    /// Returns the path of the public key of a CA.
+    fn public_key(&self, ca: &CaSpec) -> PathBuf {
+        self.output.join("keys").join(format!("{}.pub", ca.name))
+    }
+
+    /// Creates the keys for a CA and all its children.
+    fn make_keys(&self, ca: &CaSpec) -> Result<(), Error> {
+        Key {
+            private: self.private_key(ca),
+            public: self.public_key(ca),
+            algorithm: KeyAlgorithm::Rsa,
+            format: Format::Der,
+        }.run()?;
+        for child in &ca.children {
+            self.make_keys(child)?;
+        }
+        Ok(())
+    }
+
+    /// Creates and publishes all objects issued by a CA.
+    ///
+    /// The CA certificate itself must already have been created. Continues
+    /// with all the child CAs.
+    fn publish_ca(
+        &mut self, ca: &CaSpec, ca_uri: &uri::Rsync, repo: &uri::Rsync
+    ) -> Result<(), Error> {
+        let crl_uri = join_uri(repo, &format!("{}.crl", ca.name))?;
+        let mft_uri = join_uri(repo, &format!("{}.mft", ca.name))?;
+        let mut files = Vec::new();
+
+        let mut children = Vec::new();
+        for child in &ca.children {
+            let child_uri = join_uri(repo, &format!("{}.cer", child.name))?;
+            let child_repo = join_uri(repo, &format!("{}/", child.name))?;
+            let output = self.publish_path(&child_uri)?;
+            Cert {
+                issuer_key: self.private_key(ca),
+                issuer_cert: None,
+                subject_key: self.public_key(child),
+                serial: Some(self.next_serial()),
+                not_before: None,
+                not_after: None,
+                valid_days: Some(Period::days(self.days)),
+                trim_resources: false,
+                trim_overclaim: false,
+                allow_overclaim: false,
+                crl_uri: Some(crl_uri.clone()),
+                ca_issuer: Some(ca_uri.clone()),
+                ca_repository: child_repo.clone(),
+                rpki_manifest: join_uri(
+                    &child_repo, &format!("{}.mft", child.name)
+                )?,
+                rpki_notify: self.rrdp_notify.clone(),
+                v4_resources: parse_list(&child.v4, "IPv4 resources")?,
+                inherit_v4: false,
+                v6_resources: parse_list(&child.v6, "IPv6 resources")?,
+                inherit_v6: false,
+                as_resources: parse_list(&child.asns, "AS resources")?,
+                inherit_as: false,
+                state: None,
+                corrupt: None,
+                format: Format::Der,
+                output: output.clone(),
+            }.run()?;
+            files.push(output);
+            children.push((child, child_uri, child_repo));
+        }
+
+        for (idx, roa) in ca.roas.iter().enumerate() {
+            let name = ca.roa_name(idx, roa);
+            let uri = join_uri(repo, &name)?;
+            let output = self.publish_path(&uri)?;
+            Roa {
+                issuer_key: self.private_key(ca),
+                issuer_cert: None,
+                serial: Some(self.next_serial()),
+                not_before: None,
+                not_after: None,
+                valid_days: Some(Period::days(self.days)),
+                crl_uri: Some(crl_uri.clone()),
+                ca_issuer: Some(ca_uri.clone()),
+                signed_object: Some(uri),
+                asn: parse_value(&roa.asn, "ROA ASN")?,
+                prefixes: parse_list(&roa.prefixes, "ROA prefixes")?,
+                trim_overclaim: false,
+                allow_overclaim: false,
+                state: None,
+                corrupt: None,
+                output: output.clone(),
+            }.run()?;
+            files.push(output);
+        }
+
+        for (idx, aspa) in ca.aspas.iter().enumerate() {
+            let name = ca.aspa_name(idx, aspa);
+            let uri = join_uri(repo, &name)?;
+            let output = self.publish_path(&uri)?;
+            Aspa {
+                issuer_key: self.private_key(ca),
+                serial: self.next_serial(),
+                not_before: None,
+                not_after: None,
+                valid_days: Some(Period::days(self.days)),
+                crl_uri: crl_uri.clone(),
+                ca_issuer: ca_uri.clone(),
+                signed_object: uri,
+                customer: parse_value(&aspa.customer, "ASPA customer")?,
+                providers: parse_list(&aspa.providers, "ASPA providers")?,
+                output: output.clone(),
+            }.run()?;
+            files.push(output);
+        }
+
+        if let Some(ref contact) = ca.contact {
+            let uri = join_uri(repo, &format!("{}.gbr", ca.name))?;
+            let output = self.publish_path(&uri)?;
+            Gbr {
+                issuer_key: self.private_key(ca),
+                serial: self.next_serial(),
+                not_before: None,
+                not_after: None,
+                valid_days: Some(Period::days(self.days)),
+                crl_uri: crl_uri.clone(),
+                ca_issuer: ca_uri.clone(),
+                signed_object: uri,
+                vcard: None,
+                full_name: Some(contact.full_name.clone()),
+                org: contact.org.clone(),
+                adr: contact.adr.clone(),
+                tel: contact.tel.clone(),
+                email: contact.email.clone(),
+                output: output.clone(),
+            }.run()?;
+            files.push(output);
+        }
+
+        let output = self.publish_path(&crl_uri)?;
+        Crl {
+            issuer_key: self.private_key(ca),
+            this_update: None,
+            next_update: None,
+            next_days: Some(Period::days(self.days)),
+            revoked_certs: Vec::new(),
+            revoke: Vec::new(),
+            crl_number: Some(1u64.into()),
+            state: None,
+            corrupt: None,
+            format: Format::Der,
+            output: output.clone(),
+        }.run()?;
+        files.push(output);
+
+        let output = self.publish_path(&mft_uri)?;
+        Mft {
+            issuer_key: self.private_key(ca),
+            issuer_cert: None,
+            serial: Some(self.next_serial()),
+            not_before: None,
+            not_after: None,
+            valid_days: Some(Period::days(self.days)),
+            crl_uri: Some(crl_uri),
+            ca_issuer: Some(ca_uri.clone()),
+            number: Some(1u64.into()),
+            signed_object: Some(mft_uri),
+            this_update: None,
+            next_update: None,
+            next_days: Some(Period::days(self.days)),
+            files,
+            dir: None,
+            exclude: Vec::new(),
+            state: None,
+            corrupt: None,
+            output,
+        }.run()?;
+
+        for (child, child_uri, child_repo) in children {
+            self.publish_ca(child, &child_uri, &child_repo)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the path in the output directory for an rsync URI.
+    ///
+    /// Creates all the parent directories of that path, too.
+    fn publish_path(&self, uri: &uri::Rsync) -> Result<PathBuf, Error> {
+        let mut res = self.output.join("repository");
+        res.push(uri.canonical_authority().as_ref());
+        res.push(uri.module_name());
+        res.push(uri.path());
+        if let Some(parent) = res.parent() {
+            self.create_dir(parent)?;
+        }
+        Ok(res)
+    }
+
+    /// Creates a directory and all its parents.
+    fn create_dir(&self, path: &Path) -> Result<(), Error> {
+        fs::create_dir_all(path).map_err(|err| {
+            Error::Io(format!(
+                "Failed to create directory {}: {}", path.display(), err
+            ))
+        })
+    }
+}
+
+
+//------------ Spec ----------------------------------------------------------
+
+/// The description of a repository.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Spec {
+    /// The rsync URI of the directory all CA repositories are placed under.
+    rsync_base: String,
+
+    /// The RRDP notification URI to include in CA certificates.
+    rrdp_notify: Option<String>,
+
+    /// The HTTPS URI of the trust anchor certificate for the TAL.
+    tal_https_uri: Option<String>,
+
+    /// The number of days all objects are valid.
+    #[serde(default = "Spec::default_days")]
+    days: i64,
+
+    /// The trust anchor.
+    ta: CaSpec,
+}
+
+impl Spec {
+    /// Creates a description of a repository with the given trust anchor.
+    pub fn new(rsync_base: String, days: i64, ta: CaSpec) -> Self {
+        Spec {
+            rsync_base,
+            rrdp_notify: None,
+            tal_https_uri: None,
+            days,
+            ta,
+        }
+    }
+
+    /// Loads a description from a file.
+    ///
+    /// Files with an extension of `.yaml` or `.yml` are read as YAML, all
+    /// others as TOML.
+    fn load(path: &Path) -> Result<Self, Error> {
+        let data = load_file(path)?;
+        let res = match path.extension().and_then(OsStr::to_str) {
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_slice(&data).map_err(|err| err.to_string())
+            }
+            _ => {
+                toml::from_slice(&data).map_err(|err| err.to_string())
+            }
+        };
+        res.map_err(|err| {
+            Error::Usage(format!(
+                "Invalid repository description {}: {}", path.display(), err
+            ))
+        })
+    }
+
+    /// Builds the described repository in the output directory.
+    pub fn build(self, output: PathBuf) -> Result<(), Error> {
+        let base = self.rsync_base()?;
+        let rrdp_notify = match self.rrdp_notify {
+            Some(ref uri) => Some(parse_value(uri, "RRDP notify URI")?),
+            None => None
+        };
+        let tal_https_uri = match self.tal_https_uri {
+            Some(ref uri) => Some(parse_value(uri, "TAL HTTPS URI")?),
+            None => None
+        };
+        self.ta.check_names(&mut HashSet::new())?;
+
+        let mut builder = Builder {
+            output,
+            days: self.days,
+            rrdp_notify,
+            serial: 0,
+        };
+        builder.create_dir(&builder.output.join("keys"))?;
+        builder.create_dir(&builder.output.join("tals"))?;
+        builder.make_keys(&self.ta)?;
+
+        let ta = &self.ta;
+        let ta_uri = join_uri(&base, &format!("{}.cer", ta.name))?;
+        let repo = join_uri(&base, &format!("{}/", ta.name))?;
+        let output_ta = builder.publish_path(&ta_uri)?;
+        Ta {
+            key: builder.private_key(ta),
+            serial: builder.next_serial(),
+            not_before: None,
+            not_after: None,
+            valid_days: Some(Period::days(builder.days)),
+            ca_repository: repo.clone(),
+            rpki_manifest: join_uri(&repo, &format!("{}.mft", ta.name))?,
+            rpki_notify: builder.rrdp_notify.clone(),
+            v4_resources: parse_list(&ta.v4, "IPv4 resources")?,
+            v6_resources: parse_list(&ta.v6, "IPv6 resources")?,
+            as_resources: parse_list(&ta.asns, "AS resources")?,
+            tal_rsync_uri: ta_uri.clone(),
+            tal_https_uri,
+            tal_comments: Vec::new(),
+            format: Format::Der,
+            output_ta,
+            output_tal: Some(
+                builder.output.join("tals").join(format!("{}.tal", ta.name))
+            ),
+        }.run()?;
+        builder.publish_ca(ta, &ta_uri, &repo)
+    }
+
+    fn default_days() -> i64 {
+        30
+    }
+
+    /// Returns the rsync base URI.
+    fn rsync_base(&self) -> Result<uri::Rsync, Error> {
+        if !self.rsync_base.ends_with('/') {
+            return Err(Error::Usage(
+                "The rsync base URI must end in a slash.".into()
+            ))
+        }
+        parse_value(&self.rsync_base, "rsync base URI")
+    }
+}
+
+
+//------------ CaSpec --------------------------------------------------------
+
+/// The description of a CA.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CaSpec {
+    /// The name of the CA.
+    ///
+    /// This is used for the file names of the CA’s key, certificate, CRL,
+    /// and manifest as well as its repository directory.
+    name: String,
+
+    /// The IPv4 resources of the CA.
+    #[serde(default)]
+    v4: Vec<String>,
+
+    /// The IPv6 resources of the CA.
+    #[serde(default)]
+    v6: Vec<String>,
+
+    /// The AS resources of the CA.
+    #[serde(default, rename = "as")]
+    asns: Vec<String>,
+
+    /// The ROAs published by the CA.
+    #[serde(default)]
+    roas: Vec<RoaSpec>,
+
+    /// The ASPAs published by the CA.
+    #[serde(default)]
+    aspas: Vec<AspaSpec>,
+
+    /// The contact information for a Ghostbusters record.
+    contact: Option<ContactSpec>,
+
+    /// The child CAs.
+    #[serde(default)]
+    children: Vec<CaSpec>,
+}
+
+impl CaSpec {
+    /// Creates the description of a CA with the given resources.
+    pub fn new(
+        name: String, v4: Vec<String>, v6: Vec<String>, asns: Vec<String>
+    ) -> Self {
+        CaSpec {
+            name, v4, v6, asns,
+            roas: Vec::new(),
+            aspas: Vec::new(),
+            contact: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Adds a ROA to the CA.
+    pub fn push_roa(
+        &mut self, name: Option<String>, asn: String, prefixes: Vec<String>
+    ) {
+        self.roas.push(RoaSpec { name, asn, prefixes })
+    }
+
+    /// Adds a child CA.
+    pub fn push_child(&mut self, child: CaSpec) {
+        self.children.push(child)
+    }
+
+    /// Checks that the names of the CA and all its children are usable.
+    fn check_names(&self, names: &mut HashSet<String>) -> Result<(), Error> {
+        if self.name.is_empty() || !self.name.chars().all(|ch| {
+            ch.is_ascii_alphanumeric() || ch == '-' || ch == '_'
+        }) {
+            return Err(Error::Usage(
+                format!("Invalid CA name '{}'.", self.name)
+            ))
+        }
+        if !names.insert(self.name.clone()) {
+            return Err(Error::Usage(
+                format!("Duplicate CA name '{}'.", self.name)
+            ))
+        }
+        self.check_file_names()?;
+        for child in &self.children {
+            child.check_names(names)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the files published by the CA have distinct names.
+    ///
+    /// Explicitly named ROAs and ASPAs must not clash with each other, with
+    /// the default names of other objects, with the CRL, manifest, and
+    /// Ghostbusters record of the CA, or with the certificates and
+    /// repository directories of its children.
+    fn check_file_names(&self) -> Result<(), Error> {
+        let mut files = HashSet::new();
+        files.insert(format!("{}.crl", self.name));
+        files.insert(format!("{}.mft", self.name));
+        if self.contact.is_some() {
+            files.insert(format!("{}.gbr", self.name));
+        }
+        for child in &self.children {
+            files.insert(format!("{}.cer", child.name));
+            files.insert(child.name.clone());
+        }
+        let roas = self.roas.iter().enumerate().map(|(idx, roa)| {
+            (self.roa_name(idx, roa), "ROA")
+        });
+        let aspas = self.aspas.iter().enumerate().map(|(idx, aspa)| {
+            (self.aspa_name(idx, aspa), "ASPA")
+        });
+        for (name, kind) in roas.chain(aspas) {
+            if name.is_empty() || name == "." || name == ".."
+                || name.contains('/')
+            {
+                return Err(Error::Usage(format!(
+                    "Invalid {} name '{}' in CA '{}'.", kind, name, self.name
+                )))
+            }
+            if !files.insert(name.clone()) {
+                return Err(Error::Usage(format!(
+                    "{} name '{}' clashes with another file published by \
+                     CA '{}'.",
+                    kind, name, self.name
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the file name of the ROA with the given index.
+    fn roa_name(&self, idx: usize, roa: &RoaSpec) -> String {
+        match roa.name {
+            Some(ref name) => name.clone(),
+            None => format!("{}-{}.roa", self.name, idx + 1)
+        }
+    }
+
+    /// Returns the file name of the ASPA with the given index.
+    fn aspa_name(&self, idx: usize, aspa: &AspaSpec) -> String {
+        match aspa.name {
+            Some(ref name) => name.clone(),
+            None => format!("{}-{}.asa", self.name, idx + 1)
+        }
+    }
+}
+
+
+//------------ RoaSpec -------------------------------------------------------
+
+/// The description of a ROA.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RoaSpec {
+    /// The file name of the ROA.
+    name: Option<String>,
+
+    /// The AS number for the ROA.
+    asn: String,
+
+    /// The prefixes for the ROA.
+    prefixes: Vec<String>,
+}
+
+
+//------------ AspaSpec ------------------------------------------------------
+
+/// The description of an ASPA.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct AspaSpec {
+    /// The file name of the ASPA.
+    name: Option<String>,
+
+    /// The customer AS number.
+    customer: String,
+
+    /// The provider AS numbers.
+    providers: Vec<String>,
+}
+
+
+//------------ ContactSpec ---------------------------------------------------
+
+/// The contact information for a Ghostbusters record.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ContactSpec {
+    #[serde(rename = "fn")]
+    full_name: String,
+    org: Option<String>,
+    adr: Option<String>,
+    tel: Option<String>,
+    email: Option<String>,
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Joins a path to a URI.
+fn join_uri(uri: &uri::Rsync, path: &str) -> Result<uri::Rsync, Error> {
+    uri.join(path.as_bytes()).map_err(|_| {
+        Error::Usage(format!("Invalid URI path '{}'.", path))
+    })
+}
+
+/// Parses a single value of the description.
+fn parse_value<T>(value: &str, what: &str) -> Result<T, Error>
+where T: FromStr, T::Err: fmt::Display {
+    T::from_str(value).map_err(|err| {
+        Error::Usage(format!("Invalid {} '{}': {}", what, value, err))
+    })
+}
+
+/// Parses a list of values of the description.
+fn parse_list<T>(values: &[String], what: &str) -> Result<Vec<T>, Error>
+where T: FromStr, T::Err: fmt::Display {
+    values.iter().map(|value| parse_value(value, what)).collect()
+}
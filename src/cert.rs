@@ -0,0 +1,255 @@
+//! Building trust anchor and CA certificates.
+
+use bytes::Bytes;
+use rpki::crypto::{KeyIdentifier, PublicKey, Signer};
+use rpki::repository::cert::{KeyUsage, Overclaim, TbsCert};
+use rpki::repository::resources::{AsResources, IpResources};
+use rpki::repository::x509::{Serial, Validity};
+use rpki::uri;
+use crate::error::Error;
+
+
+//------------ TaBuilder -----------------------------------------------------
+
+/// A builder for a self-signed trust anchor certificate.
+#[derive(Clone, Debug)]
+pub struct TaBuilder {
+    /// The serial number of the certificate.
+    serial_number: Serial,
+
+    /// The validity of the certificate.
+    validity: Validity,
+
+    /// The URI of the CA repository.
+    ca_repository: uri::Rsync,
+
+    /// The URI of the manifest.
+    rpki_manifest: uri::Rsync,
+
+    /// The optional URI of the RRDP notification file.
+    rpki_notify: Option<uri::Https>,
+
+    /// The IPv4 resources of the certificate.
+    v4_resources: IpResources,
+
+    /// The IPv6 resources of the certificate.
+    v6_resources: IpResources,
+
+    /// The AS resources of the certificate.
+    as_resources: AsResources,
+}
+
+impl TaBuilder {
+    pub fn new(
+        serial_number: Serial,
+        validity: Validity,
+        ca_repository: uri::Rsync,
+        rpki_manifest: uri::Rsync,
+    ) -> Self {
+        TaBuilder {
+            serial_number,
+            validity,
+            ca_repository,
+            rpki_manifest,
+            rpki_notify: None,
+            v4_resources: IpResources::missing(),
+            v6_resources: IpResources::missing(),
+            as_resources: AsResources::missing(),
+        }
+    }
+
+    /// Sets the URI of the RRDP notification file.
+    pub fn set_rpki_notify(&mut self, uri: Option<uri::Https>) {
+        self.rpki_notify = uri
+    }
+
+    /// Sets the IPv4 resources of the certificate.
+    pub fn set_v4_resources(&mut self, resources: IpResources) {
+        self.v4_resources = resources
+    }
+
+    /// Sets the IPv6 resources of the certificate.
+    pub fn set_v6_resources(&mut self, resources: IpResources) {
+        self.v6_resources = resources
+    }
+
+    /// Sets the AS resources of the certificate.
+    pub fn set_as_resources(&mut self, resources: AsResources) {
+        self.as_resources = resources
+    }
+
+    /// Signs the certificate with the given key and returns its encoding.
+    pub fn build<S: Signer>(
+        self, signer: &S, key: &S::KeyId
+    ) -> Result<Bytes, Error> {
+        let key_pub = signer.get_key_info(key)?;
+        let mut cert = TbsCert::new(
+            self.serial_number,
+            key_pub.to_subject_name(),
+            self.validity,
+            None,
+            key_pub.clone(),
+            KeyUsage::Ca,
+            Overclaim::Refuse,
+        );
+        cert.set_basic_ca(Some(true));
+        cert.set_authority_key_identifier(Some(key_pub.key_identifier()));
+        cert.set_ca_repository(Some(self.ca_repository));
+        cert.set_rpki_manifest(Some(self.rpki_manifest));
+        cert.set_rpki_notify(self.rpki_notify);
+        cert.set_v4_resources(self.v4_resources);
+        cert.set_v6_resources(self.v6_resources);
+        cert.set_as_resources(self.as_resources);
+        Ok(cert.into_cert(signer, key)?.to_captured().into_bytes())
+    }
+}
+
+
+//------------ CaCertBuilder -------------------------------------------------
+
+/// A builder for a CA certificate.
+#[derive(Clone, Debug)]
+pub struct CaCertBuilder {
+    /// The serial number of the certificate.
+    serial_number: Serial,
+
+    /// The validity of the certificate.
+    validity: Validity,
+
+    /// The public key of the subject.
+    subject_key: PublicKey,
+
+    /// The URI of the CRL of the issuer.
+    crl_uri: uri::Rsync,
+
+    /// The URI of the issuer’s certificate.
+    ca_issuer: uri::Rsync,
+
+    /// The URI of the CA repository.
+    ca_repository: uri::Rsync,
+
+    /// The URI of the manifest.
+    rpki_manifest: uri::Rsync,
+
+    /// The optional URI of the RRDP notification file.
+    rpki_notify: Option<uri::Https>,
+
+    /// Whether to include the subject information access extension.
+    sia: bool,
+
+    /// The IPv4 resources of the certificate.
+    v4_resources: IpResources,
+
+    /// The IPv6 resources of the certificate.
+    v6_resources: IpResources,
+
+    /// The AS resources of the certificate.
+    as_resources: AsResources,
+
+    /// How to treat resources not covered by the issuer.
+    overclaim: Overclaim,
+
+    /// The authority key identifier of the certificate.
+    ///
+    /// If this is `None`, the key identifier of the issuer’s key is used.
+    authority_key_identifier: Option<KeyIdentifier>,
+}
+
+impl CaCertBuilder {
+    pub fn new(
+        serial_number: Serial,
+        validity: Validity,
+        subject_key: PublicKey,
+        crl_uri: uri::Rsync,
+        ca_issuer: uri::Rsync,
+        ca_repository: uri::Rsync,
+        rpki_manifest: uri::Rsync,
+    ) -> Self {
+        CaCertBuilder {
+            serial_number,
+            validity,
+            subject_key,
+            crl_uri,
+            ca_issuer,
+            ca_repository,
+            rpki_manifest,
+            rpki_notify: None,
+            sia: true,
+            v4_resources: IpResources::missing(),
+            v6_resources: IpResources::missing(),
+            as_resources: AsResources::missing(),
+            overclaim: Overclaim::Refuse,
+            authority_key_identifier: None,
+        }
+    }
+
+    /// Sets the URI of the RRDP notification file.
+    pub fn set_rpki_notify(&mut self, uri: Option<uri::Https>) {
+        self.rpki_notify = uri
+    }
+
+    /// Sets whether the subject information access extension is included.
+    ///
+    /// Leaving it out makes the certificate invalid.
+    pub fn set_sia(&mut self, sia: bool) {
+        self.sia = sia
+    }
+
+    /// Sets the IPv4 resources of the certificate.
+    pub fn set_v4_resources(&mut self, resources: IpResources) {
+        self.v4_resources = resources
+    }
+
+    /// Sets the IPv6 resources of the certificate.
+    pub fn set_v6_resources(&mut self, resources: IpResources) {
+        self.v6_resources = resources
+    }
+
+    /// Sets the AS resources of the certificate.
+    pub fn set_as_resources(&mut self, resources: AsResources) {
+        self.as_resources = resources
+    }
+
+    /// Sets how resources not covered by the issuer are to be treated.
+    pub fn set_overclaim(&mut self, overclaim: Overclaim) {
+        self.overclaim = overclaim
+    }
+
+    /// Overrides the authority key identifier of the certificate.
+    pub fn set_authority_key_identifier(&mut self, key_id: KeyIdentifier) {
+        self.authority_key_identifier = Some(key_id)
+    }
+
+    /// Signs the certificate with the issuer’s key and returns its encoding.
+    pub fn build<S: Signer>(
+        self, signer: &S, issuer_key: &S::KeyId
+    ) -> Result<Bytes, Error> {
+        let issuer_pub = signer.get_key_info(issuer_key)?;
+        let mut cert = TbsCert::new(
+            self.serial_number,
+            issuer_pub.to_subject_name(),
+            self.validity,
+            None,
+            self.subject_key,
+            KeyUsage::Ca,
+            self.overclaim,
+        );
+        cert.set_basic_ca(Some(true));
+        cert.set_authority_key_identifier(Some(
+            self.authority_key_identifier.unwrap_or_else(|| {
+                issuer_pub.key_identifier()
+            })
+        ));
+        cert.set_crl_uri(Some(self.crl_uri));
+        cert.set_ca_issuer(Some(self.ca_issuer));
+        if self.sia {
+            cert.set_ca_repository(Some(self.ca_repository));
+            cert.set_rpki_manifest(Some(self.rpki_manifest));
+            cert.set_rpki_notify(self.rpki_notify);
+        }
+        cert.set_v4_resources(self.v4_resources);
+        cert.set_v6_resources(self.v6_resources);
+        cert.set_as_resources(self.as_resources);
+        Ok(cert.into_cert(signer, issuer_key)?.to_captured().into_bytes())
+    }
+}
@@ -0,0 +1,262 @@
+//! The reference time.
+//!
+//! All commands take the default for their validity periods and update
+//! times from the reference time, as do the calculations of the end of
+//! validity from `--days` and of the next update from `--next-days`. It is
+//! the time given via the global `--now` option or the `MKRPKI_NOW`
+//! environment variable or, if neither is present, the time the program
+//! was started. Since it is determined only once, all objects created by
+//! one run share the same times.
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+use chrono::Duration;
+use mkrpki::Error;
+use rpki::repository::x509::Time;
+
+
+/// The reference time if it has been set.
+static NOW: OnceLock<Time> = OnceLock::new();
+
+/// Sets the reference time.
+///
+/// Only the first call has any effect.
+pub fn set_now(now: Time) {
+    let _ = NOW.set(now);
+}
+
+/// Returns the reference time.
+///
+/// If it hasn’t been set, this is the current time.
+pub fn now() -> Time {
+    NOW.get().copied().unwrap_or_else(Time::now)
+}
+
+/// Returns the given time or the reference time if there is none.
+pub fn or_now(time: Option<TimeSpec>) -> Result<Time, Error> {
+    match time {
+        Some(time) => time.resolve(),
+        None => Ok(now())
+    }
+}
+
+/// Resolves an optional time.
+pub fn resolve(time: Option<TimeSpec>) -> Result<Option<Time>, Error> {
+    time.map(TimeSpec::resolve).transpose()
+}
+
+
+//------------ TimeSpec ------------------------------------------------------
+
+/// A time given on the command line.
+///
+/// This is either an absolute time or a time relative to the reference
+/// time. Since the reference time is only known once all options have been
+/// parsed, relative times are resolved when they are used.
+#[derive(Clone, Copy, Debug)]
+pub enum TimeSpec {
+    /// An absolute time.
+    Absolute(Time),
+
+    /// The reference time plus the given offset.
+    Relative(Duration),
+}
+
+impl TimeSpec {
+    /// Returns the time.
+    pub fn resolve(self) -> Result<Time, Error> {
+        match self {
+            TimeSpec::Absolute(time) => Ok(time),
+            TimeSpec::Relative(offset) => {
+                let now = now();
+                now.checked_add_signed(offset).map(Time::new).ok_or_else(|| {
+                    Error::Usage(format!(
+                        "Time {} plus {} is out of range.", *now, offset
+                    ))
+                })
+            }
+        }
+    }
+}
+
+impl From<Time> for TimeSpec {
+    fn from(time: Time) -> Self {
+        TimeSpec::Absolute(time)
+    }
+}
+
+impl FromStr for TimeSpec {
+    type Err = String;
+
+    /// Parses a time.
+    ///
+    /// Relative times are `now` optionally followed by a signed duration,
+    /// just a signed duration, or an ISO 8601 duration such as `P1DT6H`.
+    /// Everything else is parsed as an absolute time.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let offset = match s.strip_prefix("now") {
+            Some("") => return Ok(TimeSpec::Relative(Duration::zero())),
+            Some(offset) => offset,
+            None => s,
+        };
+        if let Some(value) = offset.strip_prefix('+') {
+            parse_duration(value).map(TimeSpec::Relative)
+        }
+        else if let Some(value) = offset.strip_prefix('-') {
+            parse_duration(value).map(|value| TimeSpec::Relative(-value))
+        }
+        else if offset.starts_with('P') {
+            parse_duration(offset).map(TimeSpec::Relative)
+        }
+        else if offset.len() != s.len() {
+            Err(format!("Invalid relative time '{}'", s))
+        }
+        else {
+            Time::from_str(s).map(TimeSpec::Absolute).map_err(|err| {
+                format!("Invalid time '{}': {}", s, err)
+            })
+        }
+    }
+}
+
+
+//------------ Period --------------------------------------------------------
+
+/// A period given on the command line via `--days` or `--next-days`.
+///
+/// This is either a plain number of days or a duration as accepted by
+/// [`parse_duration`].
+#[derive(Clone, Copy, Debug)]
+pub struct Period(Duration);
+
+impl Period {
+    /// Creates a period of the given number of days.
+    pub fn days(days: i64) -> Self {
+        Period(Duration::days(days))
+    }
+
+    /// Returns the duration of the period.
+    pub fn duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if !s.is_empty() && s.bytes().all(|ch| ch.is_ascii_digit()) {
+            match i64::from_str(s) {
+                Ok(days) if days <= MAX_SECONDS / 86_400 => {
+                    Ok(Period::days(days))
+                }
+                _ => Err(format!("Invalid number of days '{}'", s))
+            }
+        }
+        else {
+            parse_duration(s).map(Period)
+        }
+    }
+}
+
+
+//------------ Durations -----------------------------------------------------
+
+/// The largest duration in seconds, about 10,000 years.
+const MAX_SECONDS: i64 = 10_000 * 366 * 86_400;
+
+/// Parses a duration.
+///
+/// The duration is either an ISO 8601 duration such as `P1DT6H` or a
+/// sequence of numbers each followed by one of the units `w`, `d`, `h`,
+/// `m`, or `s` such as `1d6h`. Years and months aren’t supported as their
+/// length varies.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let seconds = match s.strip_prefix('P') {
+        Some(iso) => parse_iso_duration(iso),
+        None if s.is_empty() => None,
+        None => parse_units(s, Units::Plain),
+    };
+    match seconds {
+        // Stay well away from the limits of both Duration and Time.
+        Some(seconds) if seconds <= MAX_SECONDS => {
+            Ok(Duration::seconds(seconds))
+        }
+        _ => Err(format!("Invalid duration '{}'", s))
+    }
+}
+
+/// Parses the part of an ISO 8601 duration after the leading `P`.
+fn parse_iso_duration(s: &str) -> Option<i64> {
+    let (date, time) = match s.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    if time == Some("") || (date.is_empty() && time.is_none()) {
+        return None
+    }
+    let date = parse_units(date, Units::IsoDate)?;
+    let time = match time {
+        Some(time) => parse_units(time, Units::IsoTime)?,
+        None => 0
+    };
+    date.checked_add(time)
+}
+
+/// Parses a sequence of numbers each followed by a unit.
+///
+/// Returns the total number of seconds or `None` if the sequence is
+/// invalid.
+fn parse_units(s: &str, units: Units) -> Option<i64> {
+    let mut res = 0i64;
+    let mut number: Option<i64> = None;
+    for ch in s.bytes() {
+        if ch.is_ascii_digit() {
+            number = Some(
+                number.unwrap_or(0).checked_mul(10)?.checked_add(
+                    i64::from(ch - b'0')
+                )?
+            );
+        }
+        else {
+            let value = number.take()?.checked_mul(units.seconds(ch)?)?;
+            res = res.checked_add(value)?;
+        }
+    }
+    if number.is_some() {
+        return None
+    }
+    Some(res)
+}
+
+
+//------------ Units ---------------------------------------------------------
+
+/// The units allowed in a part of a duration.
+#[derive(Clone, Copy, Debug)]
+enum Units {
+    /// The date part of an ISO 8601 duration: `W` and `D`.
+    IsoDate,
+
+    /// The time part of an ISO 8601 duration: `H`, `M`, and `S`.
+    IsoTime,
+
+    /// A plain duration: `w`, `d`, `h`, `m`, and `s`.
+    Plain,
+}
+
+impl Units {
+    /// Returns the number of seconds for a unit or `None` if not allowed.
+    fn seconds(self, unit: u8) -> Option<i64> {
+        match (self, unit) {
+            (Units::IsoDate, b'W') | (Units::Plain, b'w') => Some(7 * 86_400),
+            (Units::IsoDate, b'D') | (Units::Plain, b'd') => Some(86_400),
+            (Units::IsoTime, b'H') | (Units::Plain, b'h') => Some(3_600),
+            (Units::IsoTime, b'M') | (Units::Plain, b'm') => Some(60),
+            (Units::IsoTime, b'S') | (Units::Plain, b's') => Some(1),
+            _ => None
+        }
+    }
+}
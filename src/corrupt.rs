@@ -0,0 +1,202 @@
+//! Deliberately broken objects.
+//!
+//! For testing relying party software, the `cer`, `crl`, `roa`, and `mft`
+//! commands can create objects with a named defect via the `--corrupt`
+//! option. Apart from the defect, the objects are created normally, so
+//! each defect can be tested in isolation.
+
+use std::fmt;
+use std::str::FromStr;
+use bytes::Bytes;
+use chrono::Duration;
+use mkrpki::Error;
+use mkrpki::sigobj::SignedObjectBuilder;
+use rpki::crypto::KeyIdentifier;
+use rpki::repository::resources::{
+    AsBlocks, AsResources, IpBlocks, IpResources
+};
+use rpki::repository::x509::{Serial, Time, Validity};
+use rpki::uri;
+use unwrap::unwrap;
+use crate::clock;
+
+
+//------------ Defect --------------------------------------------------------
+
+/// A defect that can be applied to an object.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Defect {
+    /// The signature of the object doesn’t verify.
+    ///
+    /// For signed objects, this is the signature of the CMS structure.
+    BadSignature,
+
+    /// The authority key identifier doesn’t match the issuer’s key.
+    ///
+    /// For signed objects, this is the AKI of the EE certificate.
+    WrongAki,
+
+    /// The subject information access extension is missing.
+    ///
+    /// For signed objects, this affects the EE certificate.
+    MissingSia,
+
+    /// The certificate or the EE certificate has expired a day ago.
+    Expired,
+
+    /// The next update time of a CRL or manifest has passed a day ago.
+    Stale,
+
+    /// The hash of the first file on a manifest is wrong.
+    HashMismatch,
+
+    /// The certificate or EE certificate claims all resources.
+    ///
+    /// This is an overclaim unless the issuer holds all resources.
+    Overclaim,
+}
+
+impl Defect {
+    /// All defects with their names.
+    const ALL: &'static [(Defect, &'static str)] = &[
+        (Defect::BadSignature, "bad-signature"),
+        (Defect::WrongAki, "wrong-aki"),
+        (Defect::MissingSia, "missing-sia"),
+        (Defect::Expired, "expired"),
+        (Defect::Stale, "stale"),
+        (Defect::HashMismatch, "hash-mismatch"),
+        (Defect::Overclaim, "overclaim"),
+    ];
+
+    /// Returns the name of the defect as used on the command line.
+    pub fn name(self) -> &'static str {
+        Self::ALL.iter().find(|item| item.0 == self).map(|item| {
+            item.1
+        }).unwrap_or("")
+    }
+
+    /// Checks that the defect, if any, is supported by a command.
+    pub fn check(
+        defect: Option<Self>, command: &str, supported: &[Defect]
+    ) -> Result<(), Error> {
+        match defect {
+            Some(defect) if !supported.contains(&defect) => {
+                Err(Error::Usage(format!(
+                    "Defect '{}' is not supported by the {} command.",
+                    defect, command
+                )))
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl FromStr for Defect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.iter().find(|item| item.1 == s).map(|item| {
+            item.0
+        }).ok_or_else(|| {
+            let names: Vec<_> = Self::ALL.iter().map(|item| item.1).collect();
+            format!(
+                "Unknown defect '{}'. Possible values are {}.",
+                s, names.join(", ")
+            )
+        })
+    }
+}
+
+impl fmt::Display for Defect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Returns the encoded object, breaking its signature if requested.
+///
+/// The signature is the last element of certificates, CRLs, and signed
+/// objects alike, so flipping the bits of the last octet breaks it.
+pub fn apply_bad_signature(defect: Option<Defect>, data: Bytes) -> Bytes {
+    if defect != Some(Defect::BadSignature) {
+        return data
+    }
+    let mut res = data.to_vec();
+    if let Some(last) = res.last_mut() {
+        *last ^= 0xff;
+    }
+    res.into()
+}
+
+/// Creates the builder for a signed object with the requested defect.
+///
+/// This applies all defects that affect the EE certificate.
+pub fn sigobj_builder(
+    defect: Option<Defect>,
+    serial: Serial,
+    validity: Validity,
+    crl_uri: uri::Rsync,
+    ca_issuer: uri::Rsync,
+    signed_object: uri::Rsync,
+    issuer_key: KeyIdentifier,
+) -> SignedObjectBuilder {
+    let validity = match defect {
+        Some(Defect::Expired) => expired_validity(),
+        _ => validity
+    };
+    let signed_object = match defect {
+        Some(Defect::MissingSia) => None,
+        _ => Some(signed_object)
+    };
+    let mut res = SignedObjectBuilder::new(
+        serial, validity, crl_uri, ca_issuer, signed_object
+    );
+    if defect == Some(Defect::WrongAki) {
+        res.set_authority_key_identifier(wrong_key_identifier(issuer_key));
+    }
+    res
+}
+
+/// Returns a key identifier that differs from the given one.
+pub fn wrong_key_identifier(key_id: KeyIdentifier) -> KeyIdentifier {
+    let mut res = [0u8; 20];
+    for (target, source) in res.iter_mut().zip(key_id.as_slice()) {
+        *target = !source;
+    }
+    res.into()
+}
+
+/// Breaks a hash value.
+pub fn wrong_hash(hash: &[u8]) -> Bytes {
+    hash.iter().map(|item| !item).collect::<Vec<_>>().into()
+}
+
+/// Returns a validity that has ended a day ago.
+pub fn expired_validity() -> Validity {
+    let (this_update, next_update) = stale_updates();
+    Validity::new(this_update, next_update)
+}
+
+/// Returns this and next update times where the next update has passed.
+pub fn stale_updates() -> (Time, Time) {
+    let now = clock::now();
+    (now - Duration::days(2), now - Duration::days(1))
+}
+
+/// Returns IPv4 resources covering the whole address space.
+pub fn all_v4_resources() -> IpResources {
+    IpResources::blocks(unwrap!(IpBlocks::from_str("0.0.0.0/0")))
+}
+
+/// Returns IPv6 resources covering the whole address space.
+pub fn all_v6_resources() -> IpResources {
+    IpResources::blocks(unwrap!(IpBlocks::from_str("::/0")))
+}
+
+/// Returns AS resources covering all AS numbers.
+pub fn all_as_resources() -> AsResources {
+    AsResources::blocks(unwrap!(AsBlocks::from_str("AS0-AS4294967295")))
+}
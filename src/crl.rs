@@ -0,0 +1,79 @@
+//! Building certificate revocation lists.
+
+use bytes::Bytes;
+use rpki::crypto::{KeyIdentifier, RpkiSignatureAlgorithm, Signer};
+use rpki::repository::crl::{CrlEntry, TbsCertList};
+use rpki::repository::x509::{Serial, Time};
+use crate::error::Error;
+
+
+//------------ CrlBuilder ----------------------------------------------------
+
+/// A builder for a certificate revocation list.
+#[derive(Clone, Debug)]
+pub struct CrlBuilder {
+    /// The time of this update.
+    this_update: Time,
+
+    /// The time of the next update.
+    next_update: Time,
+
+    /// The CRL number.
+    crl_number: Serial,
+
+    /// The revoked certificates.
+    revoked_certs: Vec<CrlEntry>,
+
+    /// The authority key identifier of the CRL.
+    ///
+    /// If this is `None`, the key identifier of the issuer’s key is used.
+    authority_key_identifier: Option<KeyIdentifier>,
+}
+
+impl CrlBuilder {
+    pub fn new(
+        this_update: Time, next_update: Time, crl_number: Serial
+    ) -> Self {
+        CrlBuilder {
+            this_update,
+            next_update,
+            crl_number,
+            revoked_certs: Vec::new(),
+            authority_key_identifier: None,
+        }
+    }
+
+    /// Adds a revoked certificate.
+    pub fn push(&mut self, entry: CrlEntry) {
+        self.revoked_certs.push(entry)
+    }
+
+    /// Adds revoked certificates.
+    pub fn extend<I: IntoIterator<Item = CrlEntry>>(&mut self, iter: I) {
+        self.revoked_certs.extend(iter)
+    }
+
+    /// Overrides the authority key identifier of the CRL.
+    pub fn set_authority_key_identifier(&mut self, key_id: KeyIdentifier) {
+        self.authority_key_identifier = Some(key_id)
+    }
+
+    /// Signs the CRL with the issuer’s key and returns its encoding.
+    pub fn build<S: Signer>(
+        self, signer: &S, issuer_key: &S::KeyId
+    ) -> Result<Bytes, Error> {
+        let issuer_pub = signer.get_key_info(issuer_key)?;
+        let crl = TbsCertList::new(
+            RpkiSignatureAlgorithm::default(),
+            issuer_pub.to_subject_name(),
+            self.this_update,
+            self.next_update,
+            self.revoked_certs,
+            self.authority_key_identifier.unwrap_or_else(|| {
+                issuer_pub.key_identifier()
+            }),
+            self.crl_number
+        );
+        Ok(crl.into_crl(signer, issuer_key)?.to_captured().into_bytes())
+    }
+}
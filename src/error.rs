@@ -0,0 +1,106 @@
+//! The error type.
+//!
+//! The builders and the commands of the command line tool share the same
+//! error type. Each error falls into one of a few categories that a script
+//! can tell apart via the exit code of the tool.
+
+use std::{error, fmt};
+use rpki::crypto::SigningError;
+use rpki::crypto::signer::KeyError;
+
+
+//------------ Error ---------------------------------------------------------
+
+/// An error happened while creating or processing objects.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The command line or a description file is invalid.
+    Usage(String),
+
+    /// Reading or writing a file failed.
+    Io(String),
+
+    /// A key could not be created, loaded, or used for signing.
+    Key(String),
+
+    /// Data could not be decoded or encoded.
+    Encoding(String),
+
+    /// A certificate claims resources its issuer doesn’t have.
+    Overclaim(String),
+
+    /// Validating existing objects has found invalid ones.
+    Invalid(String),
+}
+
+impl Error {
+    /// All categories with their names and exit codes.
+    const CATEGORIES: &'static [(&'static str, i32)] = &[
+        ("usage", 2),
+        ("io", 3),
+        ("key", 4),
+        ("encoding", 5),
+        ("overclaim", 6),
+        ("invalid", 1),
+    ];
+
+    /// Returns the index of the error’s category.
+    fn index(&self) -> usize {
+        match *self {
+            Error::Usage(_) => 0,
+            Error::Io(_) => 1,
+            Error::Key(_) => 2,
+            Error::Encoding(_) => 3,
+            Error::Overclaim(_) => 4,
+            Error::Invalid(_) => 5,
+        }
+    }
+
+    /// Returns the name of the error’s category.
+    pub fn category(&self) -> &'static str {
+        Self::CATEGORIES[self.index()].0
+    }
+
+    /// Returns the exit code of the command line tool for the error.
+    pub fn exit_code(&self) -> i32 {
+        Self::CATEGORIES[self.index()].1
+    }
+
+    /// Returns the error message.
+    pub fn message(&self) -> &str {
+        match *self {
+            Error::Usage(ref msg) | Error::Io(ref msg) |
+            Error::Key(ref msg) | Error::Encoding(ref msg) |
+            Error::Overclaim(ref msg) | Error::Invalid(ref msg) => msg
+        }
+    }
+
+    /// Returns the error as a JSON object.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "category": self.category(),
+            "exit-code": self.exit_code(),
+            "message": self.message(),
+        }).to_string()
+    }
+}
+
+impl<E: fmt::Display> From<SigningError<E>> for Error {
+    fn from(err: SigningError<E>) -> Self {
+        Error::Key(format!("Signing failed: {}", err))
+    }
+}
+
+impl<E: fmt::Display> From<KeyError<E>> for Error {
+    fn from(err: KeyError<E>) -> Self {
+        Error::Key(format!("Failed to access key: {}", err))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl error::Error for Error { }
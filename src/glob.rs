@@ -0,0 +1,159 @@
+//! Simple glob patterns for file names.
+//!
+//! Patterns follow the usual shell conventions: `*` matches any sequence of
+//! characters, `?` matches a single character, and `[...]` matches one of
+//! the characters or ranges in the brackets or, if the first character is
+//! `!`, any character not listed. All other characters match themselves.
+
+use std::fmt;
+use std::str::FromStr;
+
+
+//------------ Glob ----------------------------------------------------------
+
+/// A glob pattern.
+#[derive(Clone, Debug)]
+pub struct Glob {
+    /// The pattern as given.
+    pattern: String,
+
+    /// The parsed pattern.
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    /// Returns whether the name matches the pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        let tokens = self.tokens.as_slice();
+        let name: Vec<char> = name.chars().collect();
+
+        // The token position after the last star and the name position it
+        // was last tried with. If matching fails, we go back there and let
+        // the star consume one more character.
+        let mut star: Option<(usize, usize)> = None;
+        let (mut tpos, mut npos) = (0, 0);
+        while npos < name.len() {
+            match tokens.get(tpos) {
+                Some(Token::Star) => {
+                    tpos += 1;
+                    star = Some((tpos, npos));
+                    continue
+                }
+                Some(token) if token.matches(name[npos]) => {
+                    tpos += 1;
+                    npos += 1;
+                    continue
+                }
+                _ => { }
+            }
+            match star {
+                Some((star_tpos, star_npos)) => {
+                    tpos = star_tpos;
+                    npos = star_npos + 1;
+                    star = Some((star_tpos, npos));
+                }
+                None => return false
+            }
+        }
+        tokens[tpos..].iter().all(|token| matches!(token, Token::Star))
+    }
+}
+
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut chars = s.chars();
+        while let Some(ch) = chars.next() {
+            tokens.push(match ch {
+                '*' => Token::Star,
+                '?' => Token::Any,
+                '[' => {
+                    let mut class = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') if !class.is_empty()
+                                && class != ['!'] => break,
+                            Some(ch) => class.push(ch),
+                            None => {
+                                return Err(format!(
+                                    "Unclosed '[' in pattern '{}'", s
+                                ))
+                            }
+                        }
+                    }
+                    Token::class(&class)
+                }
+                ch => Token::Char(ch),
+            })
+        }
+        Ok(Glob { pattern: s.into(), tokens })
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+
+//------------ Token ---------------------------------------------------------
+
+/// A single element of a glob pattern.
+#[derive(Clone, Debug)]
+enum Token {
+    /// A specific character.
+    Char(char),
+
+    /// Any single character.
+    Any,
+
+    /// Any sequence of characters.
+    Star,
+
+    /// A character class.
+    Class {
+        /// Whether the class matches characters not in the ranges.
+        negate: bool,
+
+        /// The inclusive character ranges of the class.
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    /// Creates a character class from the content of the brackets.
+    fn class(content: &[char]) -> Self {
+        let (negate, mut content) = match content.split_first() {
+            Some(('!', rest)) => (true, rest),
+            _ => (false, content),
+        };
+        let mut ranges = Vec::new();
+        while let Some(&start) = content.first() {
+            if content.len() > 2 && content[1] == '-' {
+                ranges.push((start, content[2]));
+                content = &content[3..];
+            }
+            else {
+                ranges.push((start, start));
+                content = &content[1..];
+            }
+        }
+        Token::Class { negate, ranges }
+    }
+
+    /// Returns whether a single character matches the token.
+    fn matches(&self, ch: char) -> bool {
+        match *self {
+            Token::Char(expected) => ch == expected,
+            Token::Any | Token::Star => true,
+            Token::Class { negate, ref ranges } => {
+                ranges.iter().any(|&(start, end)| {
+                    start <= ch && ch <= end
+                }) != negate
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Recreating a repository from a list of VRPs.
+//!
+//! The VRPs are read from the CSV or JSON output of Routinator or from the
+//! prefix assertions of a SLURM file as defined in RFC 8416. From them, a
+//! repository description is synthesized with a trust anchor, a number of
+//! CAs below it, and ROAs that result in exactly the VRPs given. This
+//! description is then built the same way as by the `build` command.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use mkrpki::Error;
+use rpki::repository::resources::Asn;
+use rpki::uri;
+use serde::Deserialize;
+use structopt::StructOpt;
+use crate::batch::{RoaEntry, csv_records, parse_asn};
+use crate::build::{CaSpec, Spec};
+use super::load_file;
+
+
+//------------ Import --------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Import {
+    /// Path to the Routinator CSV or JSON output or SLURM file.
+    #[structopt(long="input")]
+    input: PathBuf,
+
+    /// How to group VRPs into CAs: 'per-asn' (default), 'per-ta', 'single'.
+    #[structopt(long="group", default_value="per-asn")]
+    group: CaGrouping,
+
+    /// The rsync URI of the directory all CA repositories are placed under.
+    #[structopt(
+        long="rsync-base", default_value="rsync://rpki.example.com/repo/"
+    )]
+    rsync_base: uri::Rsync,
+
+    /// The number of days all objects are valid.
+    #[structopt(long="days", default_value="30")]
+    days: i64,
+
+    /// Path to the directory to write the repository into.
+    #[structopt(long="output")]
+    output: PathBuf,
+}
+
+impl Import {
+    pub fn run(self) -> Result<(), Error> {
+        let vrps = Vrp::load(&self.input)?;
+        if vrps.is_empty() {
+            return Err(Error::Usage(format!(
+                "No VRPs in {}.", self.input.display()
+            )))
+        }
+
+        let mut cas = BTreeMap::<String, CaContent>::new();
+        for vrp in vrps {
+            cas.entry(self.group.ca_name(&vrp)).or_default().push(vrp.entry);
+        }
+        let mut ta = CaSpec::new(
+            "ta".into(),
+            vec!["0.0.0.0/0".into()],
+            vec!["::/0".into()],
+            vec!["AS0-AS4294967295".into()],
+        );
+        for (name, content) in cas {
+            ta.push_child(content.into_spec(name));
+        }
+        Spec::new(
+            self.rsync_base.to_string(), self.days, ta
+        ).build(self.output)
+    }
+}
+
+
+//------------ CaGrouping ----------------------------------------------------
+
+/// How VRPs are grouped into CAs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum CaGrouping {
+    /// One CA for each AS number.
+    PerAsn,
+
+    /// One CA for each trust anchor given in the input.
+    PerTa,
+
+    /// A single CA for all VRPs.
+    Single,
+}
+
+impl CaGrouping {
+    /// Returns the name of the CA a VRP belongs to.
+    fn ca_name(self, vrp: &Vrp) -> String {
+        match self {
+            CaGrouping::PerAsn => {
+                format!("as{}", vrp.entry.asn().into_u32())
+            }
+            CaGrouping::PerTa => {
+                // Prefix the name so it can’t clash with the synthesized
+                // trust anchor named "ta".
+                match vrp.ta {
+                    Some(ref ta) => {
+                        let name: String = ta.chars().map(|ch| {
+                            if ch.is_ascii_alphanumeric() {
+                                ch.to_ascii_lowercase()
+                            }
+                            else {
+                                '-'
+                            }
+                        }).collect();
+                        format!("ta-{}", name)
+                    }
+                    None => "ca".into()
+                }
+            }
+            CaGrouping::Single => "ca".into()
+        }
+    }
+}
+
+impl FromStr for CaGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-asn" => Ok(CaGrouping::PerAsn),
+            "per-ta" => Ok(CaGrouping::PerTa),
+            "single" => Ok(CaGrouping::Single),
+            _ => Err(format!("invalid grouping '{}'", s))
+        }
+    }
+}
+
+
+//------------ CaContent -----------------------------------------------------
+
+/// The resources and ROAs of a synthesized CA.
+#[derive(Default)]
+struct CaContent {
+    /// The IPv4 prefixes of the CA.
+    v4: BTreeSet<String>,
+
+    /// The IPv6 prefixes of the CA.
+    v6: BTreeSet<String>,
+
+    /// The ROA prefixes for each AS number.
+    roas: BTreeMap<Asn, BTreeSet<String>>,
+}
+
+impl CaContent {
+    /// Adds a VRP to the CA.
+    fn push(&mut self, entry: RoaEntry) {
+        if entry.is_v4() {
+            self.v4.insert(entry.prefix());
+        }
+        else {
+            self.v6.insert(entry.prefix());
+        }
+        self.roas.entry(entry.asn()).or_default().insert(entry.roa_prefix());
+    }
+
+    /// Converts the content into the description of a CA.
+    ///
+    /// The CA gets exactly the prefixes of its VRPs and the AS numbers of
+    /// its ROAs as its resources and publishes one ROA per AS number.
+    fn into_spec(self, name: String) -> CaSpec {
+        let mut res = CaSpec::new(
+            name,
+            self.v4.into_iter().collect(),
+            self.v6.into_iter().collect(),
+            self.roas.keys().map(ToString::to_string).collect(),
+        );
+        for (asn, prefixes) in self.roas {
+            res.push_roa(
+                Some(format!("{}.roa", asn)), asn.to_string(),
+                prefixes.into_iter().collect()
+            );
+        }
+        res
+    }
+}
+
+
+//------------ Vrp -----------------------------------------------------------
+
+/// A VRP read from the input.
+struct Vrp {
+    /// The AS number, prefix, and max length.
+    entry: RoaEntry,
+
+    /// The name of the trust anchor the VRP was derived from, if known.
+    ta: Option<String>,
+}
+
+impl Vrp {
+    /// Loads the VRPs from a file.
+    ///
+    /// Files with the extension `.json` are read as either Routinator JSON
+    /// output or SLURM, depending on their content. All others are read as
+    /// Routinator CSV output.
+    fn load(path: &Path) -> Result<Vec<Self>, Error> {
+        let data = load_file(path)?;
+        let res = match path.extension().and_then(OsStr::to_str) {
+            Some("json") => Self::from_json(&data),
+            _ => Self::from_csv(&data),
+        };
+        res.map_err(|err| {
+            Error::Usage(format!("Invalid VRPs {}: {}", path.display(), err))
+        })
+    }
+
+    /// Parses VRPs from Routinator JSON output or a SLURM file.
+    fn from_json(data: &[u8]) -> Result<Vec<Self>, String> {
+        let json: JsonVrps = serde_json::from_slice(data).map_err(|err| {
+            err.to_string()
+        })?;
+        match json {
+            JsonVrps::Routinator { roas } => {
+                roas.into_iter().map(|roa| {
+                    Ok(Vrp {
+                        entry: RoaEntry::new(
+                            parse_asn(&roa.asn)?, &roa.prefix,
+                            Some(roa.max_length)
+                        )?,
+                        ta: roa.ta,
+                    })
+                }).collect()
+            }
+            JsonVrps::Slurm { assertions } => {
+                assertions.prefix_assertions.into_iter().map(|item| {
+                    Ok(Vrp {
+                        entry: RoaEntry::new(
+                            item.asn.into(), &item.prefix,
+                            item.max_prefix_length
+                        )?,
+                        ta: None,
+                    })
+                }).collect()
+            }
+        }
+    }
+
+    /// Parses VRPs from Routinator CSV output.
+    ///
+    /// The columns are the AS number, the prefix, the max length, and the
+    /// trust anchor.
+    fn from_csv(data: &[u8]) -> Result<Vec<Self>, String> {
+        csv_records(data)?.into_iter().map(|(line, fields)| {
+            Ok(Vrp {
+                entry: RoaEntry::from_fields(&fields).map_err(|err| {
+                    format!("line {}: {}", line, err)
+                })?,
+                ta: fields.get(3).map(|ta| ta.to_string()),
+            })
+        }).collect()
+    }
+}
+
+
+//------------ JSON Input ----------------------------------------------------
+
+/// The content of a JSON input file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonVrps {
+    /// Routinator’s JSON output.
+    Routinator {
+        roas: Vec<RoutinatorVrp>,
+    },
+
+    /// A SLURM file.
+    Slurm {
+        #[serde(rename = "locallyAddedAssertions")]
+        assertions: SlurmAssertions,
+    },
+}
+
+/// A VRP in Routinator’s JSON output.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoutinatorVrp {
+    asn: String,
+    prefix: String,
+    max_length: u8,
+    ta: Option<String>,
+}
+
+/// The locally added assertions of a SLURM file.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SlurmAssertions {
+    prefix_assertions: Vec<SlurmPrefixAssertion>,
+}
+
+/// A prefix assertion of a SLURM file.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SlurmPrefixAssertion {
+    asn: u32,
+    prefix: String,
+    max_prefix_length: Option<u8>,
+}
@@ -0,0 +1,646 @@
+//! Inspecting RPKI objects.
+//!
+//! The object is decoded using the rpki crate and converted into a simple
+//! tree of values which is then either printed as indented text or as JSON.
+
+use std::fmt;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use bcder::{Mode, OctetString, Oid, Tag};
+use bcder::decode::{Constructed, DecodeError, Source};
+use bcder::encode::Values;
+use bytes::Bytes;
+use chrono::SecondsFormat;
+use mkrpki::Error;
+use rpki::crypto::PublicKey;
+use rpki::oid;
+use rpki::repository::aspa::Aspa;
+use rpki::repository::cert::{Cert, KeyUsage};
+use rpki::repository::crl::Crl;
+use rpki::repository::manifest::Manifest;
+use rpki::repository::resources::{AsResources, IpResources};
+use rpki::repository::roa::Roa;
+use rpki::repository::sigobj::SignedObject;
+use rpki::repository::x509::{Name, Serial, Time};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use structopt::StructOpt;
+use super::{
+    pem, write_stdout, TalContent, CT_GHOSTBUSTERS, CT_SIGNED_CHECKLIST,
+    CT_TAK
+};
+
+
+//------------ Inspect -------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Inspect {
+    /// Print the object as JSON.
+    #[structopt(long="json")]
+    json: bool,
+
+    /// Path to the object to inspect.
+    #[structopt(name="path", parse(from_os_str))]
+    path: PathBuf,
+}
+
+impl Inspect {
+    pub fn run(self) -> Result<(), Error> {
+        let data = Bytes::from(pem::load_der(&self.path)?);
+        let kind = match self.path.extension().and_then(OsStr::to_str) {
+            Some("cer") => Kind::Cert,
+            Some("crl") => Kind::Crl,
+            Some("roa") | Some("mft") | Some("asa") | Some("gbr")
+            | Some("sig") | Some("tak") => Kind::SignedObject,
+            Some("tal") => Kind::Tal,
+            _ => Kind::sniff(&data),
+        };
+        let value = match kind.inspect(data) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(Error::Encoding(format!(
+                    "Invalid object {}: {}", self.path.display(), err
+                )))
+            }
+        };
+        if self.json {
+            match serde_json::to_string_pretty(&value) {
+                Ok(json) => write_stdout(&format!("{}\n", json))?,
+                Err(err) => {
+                    return Err(Error::Encoding(
+                        format!("Failed to encode JSON: {}", err)
+                    ))
+                }
+            }
+        }
+        else {
+            let mut text = String::new();
+            value.write_text(0, &mut text);
+            write_stdout(&text)?;
+        }
+        Ok(())
+    }
+}
+
+
+//------------ Kind ----------------------------------------------------------
+
+/// The kinds of objects we can inspect.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Cert,
+    Crl,
+    SignedObject,
+    Tal,
+}
+
+impl Kind {
+    /// Determines the kind of object from its content.
+    fn sniff(data: &Bytes) -> Self {
+        if SignedObject::decode(data.clone(), false).is_ok() {
+            Kind::SignedObject
+        }
+        else if Cert::decode(data.clone()).is_ok() {
+            Kind::Cert
+        }
+        else if Crl::decode(data.clone()).is_ok() {
+            Kind::Crl
+        }
+        else {
+            Kind::Tal
+        }
+    }
+
+    /// Decodes the object and converts it into a value.
+    fn inspect(self, data: Bytes) -> Result<Value, String> {
+        match self {
+            Kind::Cert => inspect_cert(data),
+            Kind::Crl => inspect_crl(data),
+            Kind::SignedObject => inspect_signed_object(data),
+            Kind::Tal => inspect_tal(data),
+        }
+    }
+}
+
+
+//------------ Inspecting the various objects --------------------------------
+
+fn inspect_cert(data: Bytes) -> Result<Value, String> {
+    let cert = Cert::decode(data).map_err(|err| err.to_string())?;
+    let kind = if cert.extended_key_usage().is_some() {
+        "router certificate"
+    }
+    else if cert.is_self_signed() {
+        "trust anchor certificate"
+    }
+    else if cert.is_ca() {
+        "CA certificate"
+    }
+    else {
+        "EE certificate"
+    };
+    let mut res = Object::new("type", kind);
+    cert_fields(&cert, &mut res);
+    Ok(res.into())
+}
+
+fn inspect_crl(data: Bytes) -> Result<Value, String> {
+    let crl = Crl::decode(data).map_err(|err| err.to_string())?;
+    let crl = crl.as_cert_list();
+    let mut revoked = Vec::new();
+    for entry in crl.revoked_certs().iter() {
+        let entry = entry.encode().to_captured(Mode::Der);
+        let (serial, date) = Mode::Der.decode(entry.as_slice(), |cons| {
+            cons.take_sequence(|cons| {
+                Ok((Serial::take_from(cons)?, Time::take_from(cons)?))
+            })
+        }).map_err(|err| err.to_string())?;
+        let mut item = Object::new("serial", serial);
+        item.push("revocation-date", time(date));
+        revoked.push(item.into());
+    }
+    let mut res = Object::new("type", "CRL");
+    res.push("issuer", name(crl.issuer()));
+    res.push(
+        "authority-key-identifier", crl.authority_key_identifier()
+    );
+    res.push("crl-number", crl.crl_number());
+    res.push("this-update", time(crl.this_update()));
+    res.push("next-update", time(crl.next_update()));
+    res.push("revoked", Value::List(revoked));
+    Ok(res.into())
+}
+
+fn inspect_signed_object(data: Bytes) -> Result<Value, String> {
+    let obj = SignedObject::decode(data.clone(), false).map_err(|err| {
+        err.to_string()
+    })?;
+    let content_type = obj.content_type();
+    let mut content = Object::default();
+    let kind = if content_type == &oid::ROUTE_ORIGIN_AUTHZ {
+        let roa = Roa::decode(data, false).map_err(|err| err.to_string())?;
+        let roa = roa.content();
+        content.push("asn", roa.as_id());
+        content.push("prefixes", Value::List(roa.iter().map(|addr| {
+            format!(
+                "{}/{}-{}",
+                addr.address(), addr.address_length(), addr.max_length()
+            ).into()
+        }).collect()));
+        "ROA"
+    }
+    else if content_type == &oid::CT_RPKI_MANIFEST {
+        let mft = Manifest::decode(data, false).map_err(|err| {
+            err.to_string()
+        })?;
+        let mft = mft.content();
+        content.push("manifest-number", mft.manifest_number());
+        content.push("this-update", time(mft.this_update()));
+        content.push("next-update", time(mft.next_update()));
+        content.push("files", Value::List(mft.iter().map(|item| {
+            let mut file = Object::new(
+                "name", String::from_utf8_lossy(item.file()).as_ref()
+            );
+            file.push("hash", hex(item.hash()));
+            file.into()
+        }).collect()));
+        "manifest"
+    }
+    else if content_type == &oid::CT_ASPA {
+        let aspa = Aspa::decode(data, false).map_err(|err| {
+            err.to_string()
+        })?;
+        let aspa = aspa.content();
+        content.push("customer", aspa.customer_as());
+        content.push("providers", Value::List(
+            aspa.provider_as_set().iter().map(Value::from_display).collect()
+        ));
+        "ASPA"
+    }
+    else if content_type == &CT_GHOSTBUSTERS {
+        content.push(
+            "vcard",
+            String::from_utf8_lossy(
+                obj.content().to_bytes().as_ref()
+            ).as_ref()
+        );
+        "Ghostbusters record"
+    }
+    else if content_type == &CT_SIGNED_CHECKLIST {
+        rsc_content(obj.content(), &mut content)?;
+        "RPKI signed checklist"
+    }
+    else if content_type == &CT_TAK {
+        tak_content(obj.content(), &mut content)?;
+        "trust anchor key"
+    }
+    else {
+        content.push("length", obj.content().len());
+        "signed object"
+    };
+
+    let mut res = Object::new("type", kind);
+    res.push("content-type", content_type);
+    if let Some(signing_time) = obj.signing_time() {
+        res.push("signing-time", time(signing_time));
+    }
+    res.push("content", content);
+    let mut ee = Object::default();
+    cert_fields(obj.cert(), &mut ee);
+    res.push("ee-certificate", ee);
+    Ok(res.into())
+}
+
+fn inspect_tal(data: Bytes) -> Result<Value, String> {
+    let text = match std::str::from_utf8(data.as_ref()) {
+        Ok(text) => text,
+        Err(_) => return Err("unknown object type".into())
+    };
+    let tal = TalContent::from_text(text)?;
+    let mut res = Object::new("type", "trust anchor locator");
+    tal_fields(&tal, &mut res);
+    Ok(res.into())
+}
+
+/// Adds the fields of a certificate.
+fn cert_fields(cert: &Cert, res: &mut Object) {
+    res.push("serial", cert.serial_number());
+    res.push("issuer", name(cert.issuer()));
+    res.push("subject", name(cert.subject()));
+    res.push("not-before", time(cert.validity().not_before()));
+    res.push("not-after", time(cert.validity().not_after()));
+    res.push("key-algorithm", format!(
+        "{:?}", cert.subject_public_key_info().algorithm()
+    ));
+    res.push("subject-key-identifier", cert.subject_key_identifier());
+    if let Some(aki) = cert.authority_key_identifier() {
+        res.push("authority-key-identifier", aki);
+    }
+    res.push("key-usage", match cert.key_usage() {
+        KeyUsage::Ca => "CA",
+        KeyUsage::Ee => "EE",
+    });
+    if let Some(uri) = cert.crl_uri() {
+        res.push("crl", uri);
+    }
+    if let Some(uri) = cert.ca_issuer() {
+        res.push("ca-issuer", uri);
+    }
+    if let Some(uri) = cert.ca_repository() {
+        res.push("ca-repository", uri);
+    }
+    if let Some(uri) = cert.rpki_manifest() {
+        res.push("rpki-manifest", uri);
+    }
+    if let Some(uri) = cert.rpki_notify() {
+        res.push("rpki-notify", uri);
+    }
+    if let Some(uri) = cert.signed_object() {
+        res.push("signed-object", uri);
+    }
+    let mut resources = Object::default();
+    if let Some(value) = ip_resources(cert.v4_resources(), true) {
+        resources.push("v4", value);
+    }
+    if let Some(value) = ip_resources(cert.v6_resources(), false) {
+        resources.push("v6", value);
+    }
+    if let Some(value) = as_resources(cert.as_resources()) {
+        resources.push("as", value);
+    }
+    res.push("resources", resources);
+}
+
+/// Adds the fields of a TAL.
+fn tal_fields(tal: &TalContent, res: &mut Object) {
+    if !tal.comments.is_empty() {
+        res.push("comments", Value::List(
+            tal.comments.iter().map(|item| item.as_str().into()).collect()
+        ));
+    }
+    res.push("uris", Value::List(
+        tal.uris.iter().map(|item| item.as_str().into()).collect()
+    ));
+    res.push("key-algorithm", format!("{:?}", tal.key.algorithm()));
+    res.push("key-identifier", tal.key.key_identifier());
+}
+
+/// Adds the content of an RPKI Signed Checklist.
+fn rsc_content(
+    content: &OctetString, res: &mut Object
+) -> Result<(), String> {
+    let files = Mode::Der.decode(content.to_bytes(), |cons| {
+        cons.take_sequence(|cons| {
+            cons.take_opt_constructed_if(Tag::CTX_0, |cons| cons.skip_all())?;
+            cons.take_sequence(|cons| cons.skip_all())?; // resources
+            cons.take_sequence(|cons| cons.skip_all())?; // digestAlgorithm
+            cons.take_sequence(|cons| {
+                let mut files = Vec::new();
+                while let Some(file) = cons.take_opt_sequence(|cons| {
+                    let name = cons.take_opt_value_if(
+                        Tag::IA5_STRING, |content| {
+                            content.as_primitive()?.take_all()
+                        }
+                    )?;
+                    let hash = OctetString::take_from(cons)?;
+                    Ok((name, hash))
+                })? {
+                    files.push(file)
+                }
+                Ok(files)
+            })
+        })
+    }).map_err(|err| err.to_string())?;
+    res.push("files", Value::List(files.into_iter().map(|(name, hash)| {
+        let mut file = Object::default();
+        if let Some(name) = name {
+            file.push("name", String::from_utf8_lossy(&name).as_ref());
+        }
+        file.push("hash", hex(&hash.to_bytes()));
+        file.into()
+    }).collect()));
+    Ok(())
+}
+
+/// Adds the content of a Trust Anchor Key object.
+fn tak_content(
+    content: &OctetString, res: &mut Object
+) -> Result<(), String> {
+    let (current, predecessor, successor) = Mode::Der.decode(
+        content.to_bytes(), |cons| {
+            cons.take_sequence(|cons| {
+                cons.take_opt_primitive_if(Tag::INTEGER, |prim| {
+                    prim.skip_all()
+                })?;
+                let current = take_tak_key(cons)?;
+                let predecessor = cons.take_opt_constructed_if(
+                    Tag::CTX_0, take_tak_key
+                )?;
+                let successor = cons.take_opt_constructed_if(
+                    Tag::CTX_1, take_tak_key
+                )?;
+                Ok((current, predecessor, successor))
+            })
+        }
+    ).map_err(|err| err.to_string())?;
+    let mut key = Object::default();
+    tal_fields(&current, &mut key);
+    res.push("current", key);
+    if let Some(predecessor) = predecessor {
+        let mut key = Object::default();
+        tal_fields(&predecessor, &mut key);
+        res.push("predecessor", key);
+    }
+    if let Some(successor) = successor {
+        let mut key = Object::default();
+        tal_fields(&successor, &mut key);
+        res.push("successor", key);
+    }
+    Ok(())
+}
+
+/// Takes a TAK key from the beginning of a constructed value.
+fn take_tak_key<S: Source>(
+    cons: &mut Constructed<S>
+) -> Result<TalContent, DecodeError<S::Error>> {
+    cons.take_sequence(|cons| {
+        let comments = take_strings(cons, Tag::UTF8_STRING)?;
+        let uris = take_strings(cons, Tag::IA5_STRING)?;
+        let key = PublicKey::take_from(cons)?;
+        Ok(TalContent { comments, uris, key })
+    })
+}
+
+/// Takes a sequence of strings with the given tag.
+fn take_strings<S: Source>(
+    cons: &mut Constructed<S>, tag: Tag
+) -> Result<Vec<String>, DecodeError<S::Error>> {
+    cons.take_sequence(|cons| {
+        let mut res = Vec::new();
+        while let Some(item) = cons.take_opt_value_if(tag, |content| {
+            content.as_primitive()?.take_all()
+        })? {
+            res.push(String::from_utf8_lossy(&item).into_owned())
+        }
+        Ok(res)
+    })
+}
+
+
+//------------ Value ---------------------------------------------------------
+
+/// A value describing an object or a part of it.
+enum Value {
+    /// A simple value.
+    Text(String),
+
+    /// A list of values.
+    List(Vec<Value>),
+
+    /// An object with named fields.
+    Object(Object),
+}
+
+impl Value {
+    fn from_display(value: impl fmt::Display) -> Self {
+        Value::Text(value.to_string())
+    }
+
+    /// Writes the value as indented text.
+    fn write_text(&self, indent: usize, target: &mut String) {
+        match *self {
+            Value::Text(ref text) => {
+                for line in text.lines() {
+                    target.push_str(&format!("{:indent$}{}\n", "", line));
+                }
+            }
+            Value::List(ref list) => {
+                for item in list {
+                    match *item {
+                        Value::Text(ref text) => {
+                            target.push_str(
+                                &format!("{:indent$}- {}\n", "", text)
+                            );
+                        }
+                        _ => {
+                            target.push_str(&format!("{:indent$}-\n", ""));
+                            item.write_text(indent + 2, target);
+                        }
+                    }
+                }
+            }
+            Value::Object(ref object) => {
+                for (key, value) in &object.0 {
+                    match *value {
+                        Value::Text(ref text) if !text.contains('\n') => {
+                            target.push_str(&format!(
+                                "{:indent$}{}: {}\n", "", key, text
+                            ));
+                        }
+                        Value::List(ref list) if list.is_empty() => {
+                            target.push_str(&format!(
+                                "{:indent$}{}: none\n", "", key
+                            ));
+                        }
+                        _ => {
+                            target.push_str(&format!(
+                                "{:indent$}{}:\n", "", key
+                            ));
+                            value.write_text(indent + 2, target);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: fmt::Display> From<T> for Value {
+    fn from(value: T) -> Self {
+        Value::from_display(value)
+    }
+}
+
+impl From<Object> for Value {
+    fn from(value: Object) -> Self {
+        Value::Object(value)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(
+        &self, serializer: S
+    ) -> Result<S::Ok, S::Error> {
+        match *self {
+            Value::Text(ref text) => serializer.serialize_str(text),
+            Value::List(ref list) => {
+                let mut seq = serializer.serialize_seq(Some(list.len()))?;
+                for item in list {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Value::Object(ref object) => {
+                let mut map = serializer.serialize_map(
+                    Some(object.0.len())
+                )?;
+                for (key, value) in &object.0 {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+
+//------------ Object --------------------------------------------------------
+
+/// An object with named fields in the order they were added.
+#[derive(Default)]
+struct Object(Vec<(&'static str, Value)>);
+
+impl Object {
+    /// Creates a new object with a first field.
+    fn new(key: &'static str, value: impl Into<Value>) -> Self {
+        Object(vec![(key, value.into())])
+    }
+
+    /// Adds a field to the object.
+    fn push(&mut self, key: &'static str, value: impl Into<Value>) {
+        self.0.push((key, value.into()))
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Converts a time into a value.
+fn time(time: Time) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Converts a name into a value.
+///
+/// Common names and serial numbers are shown with their usual labels, all
+/// other attributes with their OID.
+fn name(name: &Name) -> String {
+    // Names of signed objects are captured in BER mode, so we have to
+    // stick to that.
+    let name = name.encode_ref().to_captured(Mode::Ber);
+    let attrs = Mode::Ber.decode(name.as_slice(), |cons| {
+        cons.take_sequence(|cons| {
+            let mut res = Vec::new();
+            while let Some(()) = cons.take_opt_set(|cons| {
+                while let Some(()) = cons.take_opt_sequence(|cons| {
+                    let oid = Oid::take_from(cons)?;
+                    let value = cons.take_value(|_, content| {
+                        content.as_primitive()?.take_all()
+                    })?;
+                    let label = if oid == oid::AT_COMMON_NAME {
+                        "CN".into()
+                    }
+                    else if oid == oid::AT_SERIAL_NUMBER {
+                        "SERIALNUMBER".into()
+                    }
+                    else {
+                        oid.to_string()
+                    };
+                    res.push(format!(
+                        "{}={}", label, String::from_utf8_lossy(&value)
+                    ));
+                    Ok(())
+                })? { }
+                Ok(())
+            })? { }
+            Ok(res)
+        })
+    });
+    match attrs {
+        Ok(attrs) => attrs.join(", "),
+        Err(_) => "(invalid name)".into()
+    }
+}
+
+/// Converts IP resources into a value.
+///
+/// Returns `None` if the resources are missing.
+fn ip_resources(resources: &IpResources, v4: bool) -> Option<Value> {
+    if !resources.is_present() {
+        return None
+    }
+    Some(match resources.to_blocks() {
+        Ok(blocks) => Value::List(blocks.iter().map(|block| {
+            if v4 {
+                Value::from_display(block.display_v4())
+            }
+            else {
+                Value::from_display(block.display_v6())
+            }
+        }).collect()),
+        Err(_) => "inherit".into()
+    })
+}
+
+/// Converts AS resources into a value.
+///
+/// Returns `None` if the resources are missing.
+fn as_resources(resources: &AsResources) -> Option<Value> {
+    if !resources.is_present() {
+        return None
+    }
+    Some(match resources.to_blocks() {
+        Ok(blocks) => Value::List(
+            blocks.iter().map(Value::from_display).collect()
+        ),
+        Err(_) => "inherit".into()
+    })
+}
+
+/// Converts binary data into a hex string.
+fn hex(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len() * 2);
+    for ch in data {
+        res.push_str(&format!("{:02x}", ch));
+    }
+    res
+}
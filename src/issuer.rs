@@ -0,0 +1,294 @@
+//! Deriving URIs from the certificate of the issuing CA.
+//!
+//! Most of the URIs that go into a certificate or signed object follow
+//! from the certificate of the issuing CA. The CRL is published in the
+//! CA’s repository next to the manifest and under the same name, the CA
+//! certificate itself is published in the repository of its own issuer,
+//! and signed objects end up in the CA’s repository.
+//!
+//! The certificate also limits the resources that can go into certificates
+//! and signed objects issued by the CA. Resources not covered are reported
+//! and, depending on the [`Containment`], refused, trimmed, or left as is.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use bytes::Bytes;
+use rpki::crypto::PublicKey;
+use rpki::repository::cert::Cert;
+use rpki::repository::resources::{
+    AsResources, IpBlocks, IpBlocksForFamily, IpResources
+};
+use rpki::uri;
+use mkrpki::Error;
+
+
+//------------ IssuerCert ----------------------------------------------------
+
+/// The certificate of the issuing CA.
+pub struct IssuerCert {
+    /// The path the certificate was loaded from.
+    path: PathBuf,
+
+    /// The certificate.
+    cert: Cert,
+}
+
+impl IssuerCert {
+    /// Loads the certificate from a file.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let data = crate::pem::load_der(path)?;
+        match Cert::decode(Bytes::from(data)) {
+            Ok(cert) => Ok(IssuerCert { path: path.into(), cert }),
+            Err(err) => {
+                Err(Error::Encoding(format!(
+                    "Failed to decode issuer certificate {}: {}",
+                    path.display(), err
+                )))
+            }
+        }
+    }
+
+    /// Loads the certificate if a path was given.
+    pub fn load_opt(path: Option<&Path>) -> Result<Option<Self>, Error> {
+        path.map(Self::load).transpose()
+    }
+
+    /// Returns the certificate.
+    pub fn cert(&self) -> &Cert {
+        &self.cert
+    }
+
+    /// Checks that the certificate is for the given issuer key.
+    pub fn check_key(&self, key: &PublicKey) -> Result<(), Error> {
+        if self.cert.subject_public_key_info() == key {
+            Ok(())
+        }
+        else {
+            Err(Error::Usage(format!(
+                "Issuer certificate {} is not for the issuer key.",
+                self.path.display()
+            )))
+        }
+    }
+
+    /// Returns the URI of the CRL of the CA.
+    ///
+    /// This is the file named like the CA’s manifest but with the
+    /// extension `.crl` in the CA’s repository. This is the naming used
+    /// by the `build` command.
+    pub fn crl_uri(&self) -> Result<uri::Rsync, Error> {
+        let mft = self.rpki_manifest()?;
+        let stem = mft.path().rsplit('/').next().and_then(|name| {
+            name.strip_suffix(".mft")
+        }).filter(|stem| !stem.is_empty()).ok_or_else(|| {
+            Error::Usage(format!(
+                "Cannot derive the CRL URI from {} as its manifest URI {} \
+                 doesn’t end in a file name with the extension .mft.",
+                self.path.display(), mft
+            ))
+        })?;
+        join(&self.ca_repository()?, &format!("{}.crl", stem))
+    }
+
+    /// Returns the URI of the CA certificate itself.
+    ///
+    /// The certificate is published next to the CRL of its own issuer
+    /// under the file name it was loaded from. The local file thus needs
+    /// to have the same name as the published one. As a trust anchor
+    /// certificate doesn’t have a CRL, the URI can’t be determined for it.
+    pub fn ca_issuer(&self) -> Result<uri::Rsync, Error> {
+        let dir = self.cert.crl_uri().and_then(uri::Rsync::parent);
+        let dir = dir.ok_or_else(|| {
+            Error::Usage(format!(
+                "Cannot derive the CA issuer URI from {} as it has no CRL \
+                 distribution point.",
+                self.path.display()
+            ))
+        })?;
+        join(&dir, file_name(&self.path)?)
+    }
+
+    /// Returns the URI of a signed object published in the CA’s repository.
+    pub fn signed_object(&self, path: &Path) -> Result<uri::Rsync, Error> {
+        join(&self.ca_repository()?, file_name(path)?)
+    }
+
+    /// Returns the URI of the CA’s manifest.
+    pub fn rpki_manifest(&self) -> Result<uri::Rsync, Error> {
+        self.cert.rpki_manifest().cloned().ok_or_else(|| {
+            self.missing("rpkiManifest")
+        })
+    }
+
+    /// Checks that resources are covered by the certificate.
+    ///
+    /// Depending on `containment`, resources that aren’t covered lead to an
+    /// error, are removed from the resources, or are kept.
+    pub fn contain(
+        &self,
+        v4: &mut IpResources,
+        v6: &mut IpResources,
+        asn: &mut AsResources,
+        containment: Containment,
+    ) -> Result<(), Error> {
+        let mut excess = Vec::new();
+        if let Some((blocks, trimmed)) = ip_excess(
+            v4, self.cert.v4_resources()
+        ) {
+            excess.push(IpBlocksForFamily::v4(&blocks).to_string());
+            if containment == Containment::Trim {
+                *v4 = trimmed
+            }
+        }
+        if let Some((blocks, trimmed)) = ip_excess(
+            v6, self.cert.v6_resources()
+        ) {
+            excess.push(IpBlocksForFamily::v6(&blocks).to_string());
+            if containment == Containment::Trim {
+                *v6 = trimmed
+            }
+        }
+        if let (Ok(blocks), Ok(issuer)) = (
+            asn.to_blocks(), self.cert.as_resources().to_blocks()
+        ) {
+            let blocks_excess = blocks.difference(&issuer);
+            if !blocks_excess.is_empty() {
+                excess.push(blocks_excess.to_string());
+                if containment == Containment::Trim {
+                    let blocks = blocks.intersection(&issuer);
+                    *asn = if blocks.is_empty() {
+                        AsResources::missing()
+                    }
+                    else {
+                        AsResources::blocks(blocks)
+                    };
+                }
+            }
+        }
+        if excess.is_empty() {
+            return Ok(())
+        }
+
+        let message = format!(
+            "Resources not covered by issuer certificate: {}",
+            excess.join(", ")
+        );
+        match containment {
+            Containment::Refuse => Err(Error::Overclaim(format!(
+                "{}. Use --allow-overclaim to create the object anyway.",
+                message
+            ))),
+            Containment::Trim => {
+                eprintln!("Warning: {}. Trimming.", message);
+                Ok(())
+            }
+            Containment::Allow => {
+                eprintln!("Warning: {}.", message);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the CA repository URI of the certificate.
+    pub fn ca_repository(&self) -> Result<uri::Rsync, Error> {
+        self.cert.ca_repository().cloned().ok_or_else(|| {
+            self.missing("caRepository")
+        })
+    }
+
+    /// Returns the error for a missing SIA URI.
+    fn missing(&self, what: &str) -> Error {
+        Error::Usage(format!(
+            "Issuer certificate {} has no {} URI.", self.path.display(), what
+        ))
+    }
+}
+
+
+//------------ Containment ---------------------------------------------------
+
+/// What to do with resources not covered by the issuer certificate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Containment {
+    /// Refuse to create the object.
+    Refuse,
+
+    /// Print a warning and leave out the resources not covered.
+    Trim,
+
+    /// Print a warning and create the object anyway.
+    Allow,
+}
+
+impl Containment {
+    /// Returns the containment selected via the command line options.
+    pub fn from_options(trim: bool, allow_overclaim: bool) -> Self {
+        if allow_overclaim {
+            Containment::Allow
+        }
+        else if trim {
+            Containment::Trim
+        }
+        else {
+            Containment::Refuse
+        }
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Returns a URI given explicitly or derived from the issuer certificate.
+///
+/// If `uri` is given, it is returned. Otherwise, `derive` is used on the
+/// issuer certificate. If that is missing, too, an error mentioning the
+/// command line option `option` is returned.
+pub fn resolve_uri<F>(
+    uri: Option<uri::Rsync>,
+    issuer: Option<&IssuerCert>,
+    option: &str,
+    derive: F
+) -> Result<uri::Rsync, Error>
+where F: FnOnce(&IssuerCert) -> Result<uri::Rsync, Error> {
+    match (uri, issuer) {
+        (Some(uri), _) => Ok(uri),
+        (None, Some(issuer)) => derive(issuer),
+        (None, None) => {
+            Err(Error::Usage(format!(
+                "Either {} or --issuer-cert must be given.", option
+            )))
+        }
+    }
+}
+
+/// Returns the IP resources not covered by the issuer’s resources.
+///
+/// If there are any, returns them and the covered resources. Inherited
+/// resources are never checked.
+fn ip_excess(
+    resources: &IpResources, issuer: &IpResources
+) -> Option<(IpBlocks, IpResources)> {
+    let blocks = resources.to_blocks().ok()?;
+    let issuer = issuer.to_blocks().ok()?;
+    let excess = blocks.difference(&issuer);
+    if excess.is_empty() {
+        None
+    }
+    else {
+        Some((excess, IpResources::blocks(blocks.intersection(&issuer))))
+    }
+}
+
+/// Returns the file name portion of a path.
+fn file_name(path: &Path) -> Result<&str, Error> {
+    path.file_name().and_then(OsStr::to_str).ok_or_else(|| {
+        Error::Usage(format!("Invalid file name {}.", path.display()))
+    })
+}
+
+/// Adds a file name to a directory URI.
+fn join(dir: &uri::Rsync, name: &str) -> Result<uri::Rsync, Error> {
+    dir.join(name.as_bytes()).map_err(|_| {
+        Error::Usage(format!("Invalid file name {} for URI.", name))
+    })
+}
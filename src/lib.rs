@@ -0,0 +1,33 @@
+//! Making of RPKI-related objects.
+//!
+//! This crate contains the builders used by the _mkrpki_ command line tool
+//! so that RPKI objects can be created in-process, e.g., for generating
+//! fixtures in test harnesses. Each builder is given the values for the
+//! object and a signer with the issuer’s key and returns the DER encoded
+//! object.
+//!
+//! The builders are:
+//!
+//! * [`TaBuilder`] for self-signed trust anchor certificates,
+//! * [`CaCertBuilder`] for CA certificates,
+//! * [`CrlBuilder`] for certificate revocation lists,
+//! * [`RoaBuilder`] for route origin authorizations, and
+//! * [`ManifestBuilder`] for manifests.
+//!
+//! The EE certificates of ROAs and manifests are described by a
+//! [`SignedObjectBuilder`].
+//!
+//! [`SignedObjectBuilder`]: sigobj::SignedObjectBuilder
+
+pub use self::cert::{CaCertBuilder, TaBuilder};
+pub use self::crl::CrlBuilder;
+pub use self::error::Error;
+pub use self::manifest::ManifestBuilder;
+pub use self::roa::RoaBuilder;
+
+pub mod cert;
+pub mod crl;
+pub mod error;
+pub mod manifest;
+pub mod roa;
+pub mod sigobj;
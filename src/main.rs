@@ -2,39 +2,245 @@
 
 use std::io::{Read, Write};
 use std::fmt::Write as _;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::fs;
 use std::fs::File;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use chrono::Duration;
-use rpki::crypto::{DigestAlgorithm, PublicKey, RpkiSignatureAlgorithm, Signer};
-use rpki::crypto::softsigner::{OpenSslSigner, KeyId};
-use rpki::repository::cert::{KeyUsage, Overclaim, TbsCert};
-use rpki::repository::crl::{TbsCertList, CrlEntry};
-use rpki::repository::manifest::{FileAndHash, ManifestContent};
-use rpki::repository::roa::{RoaBuilder, RoaIpAddress};
-use rpki::repository::resources::{AsBlock, Asn, IpBlock};
+use bcder::{ConstOid, Mode, OctetString, Oid, Tag};
+use bcder::encode;
+use bcder::encode::{PrimitiveContent, Values};
+use bytes::Bytes;
+use mkrpki::{
+    CaCertBuilder, CrlBuilder, Error, ManifestBuilder, RoaBuilder, TaBuilder
+};
+use mkrpki::sigobj;
+use rpki::crypto::{
+    Digest, DigestAlgorithm, PublicKey, PublicKeyFormat, Signer
+};
+use rpki::crypto::softsigner::OpenSslSigner;
+use rpki::repository::aspa::{AspaBuilder, ProviderAs};
+use rpki::repository::cert::{
+    ExtendedKeyUsage, KeyUsage, Overclaim, TbsCert
+};
+use rpki::repository::crl::CrlEntry;
+use rpki::repository::roa::RoaIpAddress;
+use rpki::repository::resources::{
+    AddressFamily, AsBlock, AsBlocks, AsResources, Asn, IpBlock, IpBlocks,
+    IpResources
+};
 use rpki::repository::sigobj::SignedObjectBuilder;
-use rpki::repository::x509::{Serial, Time, Validity};
-use rpki::uri;
+use rpki::repository::x509::{Name, Serial, Validity};
+use rpki::{oid, uri};
 use structopt::StructOpt;
 use unwrap::unwrap;
+use crate::clock::{Period, TimeSpec};
+use crate::corrupt::Defect;
+use crate::glob::Glob;
+use crate::issuer::{Containment, IssuerCert, resolve_uri};
+use crate::pem::Format;
+use crate::pkcs11::{Pkcs11Signer, Pkcs11Uri};
+use crate::signer::{KeyRef, KeySigner};
+use crate::state::{CaState, Counter, take_counter};
+
+mod batch;
+mod build;
+mod clock;
+mod corrupt;
+mod glob;
+mod import;
+mod inspect;
+mod issuer;
+mod pem;
+mod pkcs11;
+mod refresh;
+mod reissue;
+mod rollover;
+mod rrdp;
+mod seeded;
+mod signer;
+mod state;
+mod validate;
 
 
 //------------ main ----------------------------------------------------------
 
 fn main() {
-    if let Err(()) = Operation::from_args().run() {
-        std::process::exit(1)
+    let argv: Vec<_> = std::env::args_os().collect();
+    let error_format = ErrorFormat::from_args(&argv);
+    let mut app = Args::clap();
+    if error_format == ErrorFormat::Json {
+        app = app.setting(structopt::clap::AppSettings::ColorNever);
+    }
+    let (args, label) = match app.get_matches_from_safe(&argv) {
+        Ok(matches) => {
+            let label = invocation_label(&argv, matches.subcommand_name());
+            (Args::from_clap(&matches), label)
+        }
+        Err(err) => {
+            if !err.use_stderr() {
+                err.exit()
+            }
+            let err = match error_format {
+                ErrorFormat::Text => Error::Usage(err.message),
+                ErrorFormat::Json => {
+                    // Drop the usage and join everything on one line.
+                    let message = err.message.split("USAGE:").next()
+                        .unwrap_or_default()
+                        .trim_start_matches("error: ");
+                    Error::Usage(
+                        message.split_whitespace().collect::<Vec<_>>()
+                            .join(" ")
+                    )
+                }
+            };
+            error_format.print(&err);
+            std::process::exit(err.exit_code())
+        }
+    };
+    let error_format = args.error_format;
+    if let Err(err) = args.run(&label) {
+        error_format.print(&err);
+        std::process::exit(err.exit_code())
     }
 }
 
+/// Returns the label for seeded random values of this invocation.
+///
+/// This is the subcommand and all its arguments, so that different
+/// commands run with the same seed produce different values.
+fn invocation_label(argv: &[OsString], subcommand: Option<&str>) -> String {
+    let args = argv.iter().map(|arg| arg.to_string_lossy());
+    let args = args.skip_while(|arg| Some(arg.as_ref()) != subcommand);
+    args.collect::<Vec<_>>().join("\0")
+}
 
-//------------ Operation -----------------------------------------------------
+
+//------------ Args ----------------------------------------------------------
 
 #[derive(StructOpt)]
 #[structopt(name="mkrpki", about="Creates RPKI objects.")]
+struct Args {
+    /// The format of error messages: 'text' (default) or 'json'.
+    #[structopt(long="error-format", default_value="text")]
+    error_format: ErrorFormat,
+
+    /// Produce the same output for the same input.
+    #[structopt(long="deterministic")]
+    deterministic: bool,
+
+    /// The seed for random values in deterministic mode (default 0).
+    #[structopt(long="seed")]
+    seed: Option<u64>,
+
+    /// The reference time for all defaults instead of the current time.
+    #[structopt(
+        long="now", env="MKRPKI_NOW", raw(allow_hyphen_values="true")
+    )]
+    now: Option<TimeSpec>,
+
+    #[structopt(subcommand)]
+    operation: Operation,
+}
+
+impl Args {
+    /// Applies the global options and runs the operation.
+    ///
+    /// The label identifies the invocation for deterministic mode.
+    fn run(self, label: &str) -> Result<(), Error> {
+        self.setup(label)?;
+        self.operation.run()
+    }
+
+    /// Applies the global options.
+    fn setup(&self, label: &str) -> Result<(), Error> {
+        if !self.deterministic {
+            if self.seed.is_some() {
+                return Err(Error::Usage(
+                    "--seed requires --deterministic.".into()
+                ))
+            }
+            clock::set_now(clock::or_now(self.now)?);
+            return Ok(())
+        }
+        let now = match self.now {
+            Some(TimeSpec::Absolute(now)) => now,
+            Some(TimeSpec::Relative(_)) => {
+                return Err(Error::Usage(
+                    "--now must be an absolute time in deterministic mode."
+                    .into()
+                ))
+            }
+            None => {
+                return Err(Error::Usage(
+                    "--deterministic requires --now or MKRPKI_NOW.".into()
+                ))
+            }
+        };
+        clock::set_now(now);
+        seeded::enable(self.seed.unwrap_or(0), label);
+        Ok(())
+    }
+}
+
+
+//------------ ErrorFormat ---------------------------------------------------
+
+/// The format for printing an error before exiting.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl ErrorFormat {
+    /// Determines the format from the raw command line arguments.
+    ///
+    /// This is necessary so that errors while parsing the command line can
+    /// be printed in the requested format, too. If the option is missing or
+    /// invalid, the default text format is used.
+    fn from_args(args: &[OsString]) -> Self {
+        let mut args = args.iter().filter_map(|arg| arg.to_str());
+        while let Some(arg) = args.next() {
+            let value = if arg == "--error-format" {
+                args.next()
+            }
+            else {
+                arg.strip_prefix("--error-format=")
+            };
+            if let Some(value) = value {
+                return Self::from_str(value).unwrap_or(ErrorFormat::Text)
+            }
+        }
+        ErrorFormat::Text
+    }
+
+    /// Prints an error in this format.
+    fn print(self, err: &Error) {
+        match self {
+            ErrorFormat::Text => eprintln!("{}", err),
+            ErrorFormat::Json => eprintln!("{}", err.to_json()),
+        }
+    }
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("Unknown error format '{}'", s))
+        }
+    }
+}
+
+
+//------------ Operation -----------------------------------------------------
+
+#[derive(StructOpt)]
 #[allow(clippy::large_enum_variant)]
 enum Operation {
     /// Creates a key pair.
@@ -49,6 +255,10 @@ enum Operation {
     #[structopt(name="cer")]
     Cert(Cert),
 
+    /// Creates a BGPsec router certificate.
+    #[structopt(name="router-cer")]
+    RouterCert(RouterCert),
+
     /// Creates a CRL.
     #[structopt(name="crl")]
     Crl(Crl),
@@ -60,17 +270,78 @@ enum Operation {
     /// Creates a manifest.
     #[structopt(name="mft")]
     Mft(Mft),
+
+    /// Creates many ROAs from a list of prefixes.
+    #[structopt(name="roa-batch")]
+    RoaBatch(batch::RoaBatch),
+
+    /// Creates an ASPA object.
+    #[structopt(name="aspa")]
+    Aspa(Aspa),
+
+    /// Creates a Ghostbusters record.
+    #[structopt(name="gbr")]
+    Gbr(Gbr),
+
+    /// Creates an RPKI Signed Checklist.
+    #[structopt(name="rsc")]
+    Rsc(Rsc),
+
+    /// Creates a Trust Anchor Key object.
+    #[structopt(name="tak")]
+    Tak(Tak),
+
+    /// Builds a complete repository from a description.
+    #[structopt(name="build")]
+    Build(build::Build),
+
+    /// Recreates a repository from a list of VRPs.
+    #[structopt(name="import")]
+    Import(import::Import),
+
+    /// Re-issues the CRLs and manifests of a repository.
+    #[structopt(name="refresh")]
+    Refresh(refresh::Refresh),
+
+    /// Rolls the key of a CA.
+    #[structopt(name="rollover")]
+    Rollover(rollover::Rollover),
+
+    /// Creates RRDP files for a repository.
+    #[structopt(name="rrdp")]
+    Rrdp(rrdp::Rrdp),
+
+    /// Prints the content of an RPKI object.
+    #[structopt(name="inspect")]
+    Inspect(inspect::Inspect),
+
+    /// Validates a repository starting from a TAL.
+    #[structopt(name="validate")]
+    Validate(validate::Validate),
 }
 
 impl Operation {
-    pub fn run(self) -> Result<(), ()> {
+    pub fn run(self) -> Result<(), Error> {
         match self {
             Operation::Key(key) => key.run(),
             Operation::Ta(ta) => ta.run(),
             Operation::Cert(cert) => cert.run(),
+            Operation::RouterCert(cert) => cert.run(),
             Operation::Crl(crl) => crl.run(),
             Operation::Roa(roa) => roa.run(),
+            Operation::RoaBatch(batch) => batch.run(),
             Operation::Mft(mft) => mft.run(),
+            Operation::Aspa(aspa) => aspa.run(),
+            Operation::Gbr(gbr) => gbr.run(),
+            Operation::Rsc(rsc) => rsc.run(),
+            Operation::Tak(tak) => tak.run(),
+            Operation::Build(build) => build.run(),
+            Operation::Import(import) => import.run(),
+            Operation::Refresh(refresh) => refresh.run(),
+            Operation::Rollover(rollover) => rollover.run(),
+            Operation::Rrdp(rrdp) => rrdp.run(),
+            Operation::Inspect(inspect) => inspect.run(),
+            Operation::Validate(validate) => validate.run(),
         }
     }
 }
@@ -87,59 +358,161 @@ struct Key {
     /// The path to the public key file.
     #[structopt(long = "public")]
     public: PathBuf,
+
+    /// The key algorithm: 'rsa' (default) or 'ecdsa-p256'.
+    #[structopt(long = "algorithm", default_value = "rsa")]
+    algorithm: KeyAlgorithm,
+
+    /// The format of the key files: 'der' (default) or 'pem'.
+    #[structopt(long = "format", default_value = "der")]
+    format: Format,
 }
 
 impl Key {
-    pub fn run(self) -> Result<(), ()> {
-        let key = match openssl::rsa::Rsa::generate(2048) {
-            Ok(key) => key,
+    pub fn run(self) -> Result<(), Error> {
+        let (private, public) = match self.algorithm {
+            KeyAlgorithm::Rsa => Self::generate_rsa()?,
+            KeyAlgorithm::EcdsaP256 => Self::generate_ecdsa_p256()?,
+        };
+        let private = self.format.encode(
+            self.algorithm.private_key_label(), &private
+        );
+        let public = self.format.encode(pem::PUBLIC_KEY, &public);
+
+        let mut file = match File::create(&self.private) {
+            Ok(file) => file,
             Err(err) => {
-                eprintln!("Failed to generate key: {}", err);
-                return Err(())
+                return Err(Error::Io(format!(
+                    "Failed to open private key file: {}", err
+                )))
             }
         };
+        if let Err(err) = file.write_all(&private) {
+            return Err(Error::Io(format!(
+                "Failed to write to private key file: {}", err
+            )))
+        }
 
-        let mut file = match File::create(&self.private) {
+        let mut file = match File::create(&self.public) {
             Ok(file) => file,
             Err(err) => {
-                eprintln!("Failed to open private key file: {}", err);
-                return Err(())
+                return Err(Error::Io(format!(
+                    "Failed to open public key file: {}", err
+                )))
+            }
+        };
+        if let Err(err) = file.write_all(&public) {
+            return Err(Error::Io(format!(
+                "Failed to write to public key file: {}", err
+            )))
+        }
+
+        eprintln!("key: {}", self.private.display());
+        eprintln!("pub:  {}", self.public.display());
+        Ok(())
+    }
+
+    /// Generates an RSA key pair and returns private and public key.
+    ///
+    /// In deterministic mode, the key is derived from the seed.
+    fn generate_rsa() -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let key = seeded::rsa_key().unwrap_or_else(|| {
+            openssl::rsa::Rsa::generate(2048)
+        });
+        let key = match key {
+            Ok(key) => key,
+            Err(err) => {
+                return Err(Error::Key(format!(
+                    "Failed to generate key: {}", err
+                )))
             }
         };
-        let buf = match key.private_key_to_der() {
+        let private = match key.private_key_to_der() {
             Ok(buf) => buf,
             Err(err) => {
-                eprintln!("Failed to extract private key: {}", err);
-                return Err(())
+                return Err(Error::Key(format!(
+                    "Failed to extract private key: {}", err
+                )))
             }
         };
-        if let Err(err) = file.write_all(&buf) {
-            eprintln!("Failed to write to private key file: {}", err);
-            return Err(())
-        }
+        let public = match key.public_key_to_der() {
+            Ok(buf) => buf,
+            Err(err) => {
+                return Err(Error::Key(format!(
+                    "Failed to extract public key: {}", err
+                )))
+            }
+        };
+        Ok((private, public))
+    }
 
-        let mut file = match File::create(&self.public) {
-            Ok(file) => file,
+    /// Generates an ECDSA P-256 key pair and returns private and public key.
+    ///
+    /// In deterministic mode, the key is derived from the seed.
+    fn generate_ecdsa_p256() -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let key = openssl::ec::EcGroup::from_curve_name(
+            openssl::nid::Nid::X9_62_PRIME256V1
+        ).and_then(|group| {
+            seeded::ec_key(&group).unwrap_or_else(|| {
+                openssl::ec::EcKey::generate(&group)
+            })
+        });
+        let key = match key {
+            Ok(key) => key,
+            Err(err) => {
+                return Err(Error::Key(format!(
+                    "Failed to generate key: {}", err
+                )))
+            }
+        };
+        let private = match key.private_key_to_der() {
+            Ok(buf) => buf,
             Err(err) => {
-                eprintln!("Failed to open public key file: {}", err);
-                return Err(())
+                return Err(Error::Key(format!(
+                    "Failed to extract private key: {}", err
+                )))
             }
         };
-        let buf = match key.public_key_to_der() {
+        let public = match key.public_key_to_der() {
             Ok(buf) => buf,
             Err(err) => {
-                eprintln!("Failed to extract public key: {}", err);
-                return Err(())
+                return Err(Error::Key(format!(
+                    "Failed to extract public key: {}", err
+                )))
             }
         };
-        if let Err(err) = file.write_all(&buf) {
-            eprintln!("Failed to write to public key file: {}", err);
-            return Err(())
+        Ok((private, public))
+    }
+}
+
+
+//------------ KeyAlgorithm --------------------------------------------------
+
+#[derive(Clone, Copy, Debug)]
+enum KeyAlgorithm {
+    Rsa,
+    EcdsaP256,
+}
+
+impl KeyAlgorithm {
+    /// Returns the PEM label for a private key of this algorithm.
+    fn private_key_label(self) -> &'static str {
+        match self {
+            KeyAlgorithm::Rsa => pem::RSA_PRIVATE_KEY,
+            KeyAlgorithm::EcdsaP256 => pem::EC_PRIVATE_KEY,
         }
+    }
+}
 
-        eprintln!("key: {}", self.private.display());
-        eprintln!("pub:  {}", self.public.display());
-        Ok(())
+impl FromStr for KeyAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rsa" => Ok(KeyAlgorithm::Rsa),
+            "ecdsa-p256" => Ok(KeyAlgorithm::EcdsaP256),
+            _ => Err(format!("Unknown key algorithm '{}'", s))
+        }
     }
 }
 
@@ -157,16 +530,16 @@ struct Ta {
     serial: Serial,
 
     /// Not-before date of the certificate. Defaults to now.
-    #[structopt(long="not-before")]
-    not_before: Option<Time>,
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
 
     /// Not-after date of the certificate.
-    #[structopt(long="not-after")]
-    not_after: Option<Time>,
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
 
-    /// Duration of validity of certificate in days.
+    /// Duration of validity of certificate, in days if just a number.
     #[structopt(long="days")]
-    valid_days: Option<i64>,
+    valid_days: Option<Period>,
 
     /// CA repository URI.
     #[structopt(long="ca-repository")]
@@ -200,6 +573,14 @@ struct Ta {
     #[structopt(long="tal-https-uri")]
     tal_https_uri: Option<uri::Https>,
 
+    /// Optional comments to include in the TAL file.
+    #[structopt(long="tal-comment")]
+    tal_comments: Vec<String>,
+
+    /// The format of the certificate: 'der' (default) or 'pem'.
+    #[structopt(long="format", default_value="der")]
+    format: Format,
+
     /// Path to file to write the certificate into.
     #[structopt(long="output")]
     output_ta: PathBuf,
@@ -210,62 +591,45 @@ struct Ta {
 }
 
 impl Ta {
-    pub fn run(self) -> Result<(), ()> {
+    pub fn run(self) -> Result<(), Error> {
         let (signer, key) = create_signer(&self.key)?;
-        let key_pub = unwrap!(signer.get_key_info(&key));
+        let key_pub = signer.get_key_info(&key)?;
 
-        let not_before = self.not_before.unwrap_or_else(Time::now);
-        let validity = if let Some(not_after) = self.not_after {
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
             Validity::new(not_before, not_after)
         }
         else if let Some(valid_days) = self.valid_days {
-            Validity::new(not_before, not_before + Duration::days(valid_days))
+            Validity::new(not_before, not_before + valid_days.duration())
         }
         else {
-            eprintln!("Either --not-after or --days must be given.");
-            return Err(())
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
         };
 
-        let mut cert = TbsCert::new(
-            self.serial,
-            key_pub.to_subject_name(),
-            validity,
-            None,
-            key_pub.clone(),
-            KeyUsage::Ca,
-            Overclaim::Refuse,
+        let mut cert = TaBuilder::new(
+            self.serial, validity, self.ca_repository, self.rpki_manifest
         );
-        cert.set_basic_ca(Some(true));
-        cert.set_authority_key_identifier(Some(key_pub.key_identifier()));
-        cert.set_ca_repository(Some(self.ca_repository));
-        cert.set_rpki_manifest(Some(self.rpki_manifest));
-        if let Some(rpki_notify) = self.rpki_notify {
-            cert.set_rpki_notify(Some(rpki_notify));
-        }
-        if !self.v4_resources.is_empty() {
-            cert.v4_resources_from_iter(self.v4_resources);
-        }
-        if !self.v6_resources.is_empty() {
-            cert.v6_resources_from_iter(self.v6_resources);
-        }
-        if !self.as_resources.is_empty() {
-            cert.as_resources_from_iter(self.as_resources);
-        }
+        cert.set_rpki_notify(self.rpki_notify);
+        cert.set_v4_resources(ip_resources(self.v4_resources, false));
+        cert.set_v6_resources(ip_resources(self.v6_resources, false));
+        cert.set_as_resources(as_resources(self.as_resources, false));
 
-        let cert = unwrap!(cert.into_cert(&signer, &key)).to_captured();
-        save_file(&self.output_ta, &cert)?;
+        let cert = cert.build(&signer, &key)?;
+        pem::save(&self.output_ta, self.format, pem::CERTIFICATE, &cert)?;
         eprintln!("TA:  {}", self.output_ta.display());
         
         if let Some(path) = self.output_tal {
-            let mut tal = format!("{}\n", self.tal_rsync_uri);
+            let mut uris = vec![self.tal_rsync_uri.to_string()];
             if let Some(uri) = self.tal_https_uri {
-                unwrap!(writeln!(tal, "{}", uri));
+                uris.push(uri.to_string());
             }
-            unwrap!(writeln!(tal, ""));
-            unwrap!(
-                writeln!(tal, "{}", base64::encode(&key_pub.to_info_bytes()))
-            );
-            save_file(&path, tal.as_bytes())?;
+            let tal = TalContent {
+                comments: self.tal_comments, uris, key: key_pub
+            };
+            save_file(&path, tal.to_text().as_bytes())?;
             eprintln!("TAL: {}", path.display());
         }
         Ok(())
@@ -281,37 +645,50 @@ struct Cert {
     #[structopt(long="issuer-key")]
     issuer_key: PathBuf,
 
+    /// Path to the certificate of the issuer to derive URIs from. Must have
+    /// the file name the certificate is published under.
+    #[structopt(long="issuer-cert")]
+    issuer_cert: Option<PathBuf>,
+
     /// Path to the public key of the certificate subject.
     #[structopt(long="subject-key")]
     subject_key: PathBuf,
 
-    /// Serial number of the certificate.
+    /// Serial number of the certificate. Taken from the state if missing.
     #[structopt(long="serial")]
-    serial: Serial,
+    serial: Option<Serial>,
 
     /// Not-before date of the certificate. Defaults to now.
-    #[structopt(long="not-before")]
-    not_before: Option<Time>,
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
 
     /// Not-after date of the certificate.
-    #[structopt(long="not-after")]
-    not_after: Option<Time>,
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
 
-    /// Duration of validity of certificate in days.
+    /// Duration of validity of certificate, in days if just a number.
     #[structopt(long="days")]
-    valid_days: Option<i64>,
+    valid_days: Option<Period>,
 
     /// Overclaiming resources should be trimmed.
     #[structopt(long="trim-resources")]
     trim_resources: bool,
 
-    /// RPKI URI of the CRL.
+    /// Leave out resources not covered by the issuer certificate.
+    #[structopt(long="trim-overclaim")]
+    trim_overclaim: bool,
+
+    /// Keep resources not covered by the issuer certificate.
+    #[structopt(long="allow-overclaim")]
+    allow_overclaim: bool,
+
+    /// RPKI URI of the CRL. Derived from the issuer certificate if missing.
     #[structopt(long="crl")]
-    crl_uri: uri::Rsync,
+    crl_uri: Option<uri::Rsync>,
 
-    /// CA issuer URI.
+    /// CA issuer URI. Derived from the issuer certificate if missing.
     #[structopt(long="ca-issuer")]
-    ca_issuer: uri::Rsync,
+    ca_issuer: Option<uri::Rsync>,
 
     /// CA repository URI.
     #[structopt(long="ca-repository")]
@@ -349,81 +726,259 @@ struct Cert {
     #[structopt(long="inherit-as")]
     inherit_as: bool,
 
+    /// Path to the directory with the state of the issuing CA.
+    #[structopt(long="state")]
+    state: Option<PathBuf>,
+
+    /// Create the certificate with the given defect.
+    #[structopt(long="corrupt")]
+    corrupt: Option<Defect>,
+
+    /// The format of the certificate: 'der' (default) or 'pem'.
+    #[structopt(long="format", default_value="der")]
+    format: Format,
+
     /// Path to file to write the certificate into.
     #[structopt(long="output")]
     output: PathBuf
 }
 
 impl Cert {
-    pub fn run(self) -> Result<(), ()> {
+    pub fn run(self) -> Result<(), Error> {
+        Defect::check(self.corrupt, "cer", &[
+            Defect::BadSignature, Defect::WrongAki, Defect::MissingSia,
+            Defect::Expired, Defect::Overclaim,
+        ])?;
+        let (signer, issuer_key) = create_signer(&self.issuer_key)?;
+        let issuer_pub = signer.get_key_info(&issuer_key)?;
+        let issuer_cert = IssuerCert::load_opt(self.issuer_cert.as_deref())?;
+        if let Some(issuer_cert) = issuer_cert.as_ref() {
+            issuer_cert.check_key(&issuer_pub)?;
+        }
+        let crl_uri = resolve_uri(
+            self.crl_uri, issuer_cert.as_ref(), "--crl", IssuerCert::crl_uri
+        )?;
+        let ca_issuer = resolve_uri(
+            self.ca_issuer, issuer_cert.as_ref(), "--ca-issuer",
+            IssuerCert::ca_issuer
+        )?;
+        let subject_key = pem::load_der(&self.subject_key)?;
+        let subject_key = match PublicKey::decode(subject_key.as_slice()) {
+            Ok(key) => key,
+            Err(err) => {
+                return Err(Error::Key(format!(
+                    "Failed to load subject public key: {}", err
+                )))
+            }
+        };
+
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
+            Validity::new(not_before, not_after)
+        }
+        else if let Some(valid_days) = self.valid_days {
+            Validity::new(not_before, not_before + valid_days.duration())
+        }
+        else {
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
+        };
+        let validity = match self.corrupt {
+            Some(Defect::Expired) => corrupt::expired_validity(),
+            _ => validity
+        };
+        let mut state = CaState::load_opt(self.state.as_deref())?;
+        let serial = take_counter(
+            state.as_mut(), Counter::Serial, self.serial
+        )?;
+
+        let mut cert = CaCertBuilder::new(
+            serial, validity, subject_key, crl_uri, ca_issuer,
+            self.ca_repository, self.rpki_manifest
+        );
+        cert.set_rpki_notify(self.rpki_notify);
+        if self.trim_resources {
+            cert.set_overclaim(Overclaim::Trim);
+        }
+        if self.corrupt == Some(Defect::WrongAki) {
+            cert.set_authority_key_identifier(
+                corrupt::wrong_key_identifier(issuer_pub.key_identifier())
+            );
+        }
+        if self.corrupt == Some(Defect::MissingSia) {
+            cert.set_sia(false);
+        }
+        let mut v4 = ip_resources(self.v4_resources, self.inherit_v4);
+        let mut v6 = ip_resources(self.v6_resources, self.inherit_v6);
+        let mut asn = as_resources(self.as_resources, self.inherit_as);
+        if let Some(ref issuer_cert) = issuer_cert {
+            let containment = Containment::from_options(
+                self.trim_overclaim, self.allow_overclaim
+            );
+            issuer_cert.contain(&mut v4, &mut v6, &mut asn, containment)?;
+        }
+        cert.set_v4_resources(v4);
+        cert.set_v6_resources(v6);
+        cert.set_as_resources(asn);
+        if self.corrupt == Some(Defect::Overclaim) {
+            cert.set_v4_resources(corrupt::all_v4_resources());
+            cert.set_v6_resources(corrupt::all_v6_resources());
+            cert.set_as_resources(corrupt::all_as_resources());
+        }
+
+        let cert = cert.build(&signer, &issuer_key)?;
+        let cert = corrupt::apply_bad_signature(self.corrupt, cert);
+        pem::save(&self.output, self.format, pem::CERTIFICATE, &cert)?;
+        eprintln!("Cer: {}", self.output.display());
+        if let Some(mut state) = state {
+            state.issue(serial, "cer", &self.output, validity.not_after());
+            state.save()?;
+        }
+        Ok(())
+    }
+}
+
+
+//------------ RouterCert ----------------------------------------------------
+
+#[derive(StructOpt)]
+struct RouterCert {
+    /// Path to the private key of the certificate issuer.
+    #[structopt(long="issuer-key")]
+    issuer_key: PathBuf,
+
+    /// Path to the ECDSA P-256 public key of the router.
+    #[structopt(long="subject-key")]
+    subject_key: PathBuf,
+
+    /// Serial number of the certificate.
+    #[structopt(long="serial")]
+    serial: Serial,
+
+    /// Not-before date of the certificate. Defaults to now.
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
+
+    /// Not-after date of the certificate.
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
+
+    /// Duration of validity of certificate, in days if just a number.
+    #[structopt(long="days")]
+    valid_days: Option<Period>,
+
+    /// RPKI URI of the CRL.
+    #[structopt(long="crl")]
+    crl_uri: uri::Rsync,
+
+    /// CA issuer URI.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: uri::Rsync,
+
+    /// The AS numbers of the router.
+    #[structopt(long="asn")]
+    asns: Vec<Asn>,
+
+    /// The BGP identifier of the router to include in the subject name.
+    #[structopt(long="router-id")]
+    router_id: Option<Ipv4Addr>,
+
+    /// Path to file to write the certificate into.
+    #[structopt(long="output")]
+    output: PathBuf
+}
+
+impl RouterCert {
+    pub fn run(self) -> Result<(), Error> {
+        let first_asn = match self.asns.first() {
+            Some(asn) => *asn,
+            None => {
+                return Err(Error::Usage(
+                    "At least one --asn must be given.".into()
+                ))
+            }
+        };
         let (signer, issuer_key) = create_signer(&self.issuer_key)?;
-        let issuer_pub = unwrap!(signer.get_key_info(&issuer_key));
-        let subject_key = load_file(&self.subject_key)?;
+        let issuer_pub = signer.get_key_info(&issuer_key)?;
+        let subject_key = pem::load_der(&self.subject_key)?;
         let subject_key = match PublicKey::decode(subject_key.as_slice()) {
             Ok(key) => key,
             Err(err) => {
-                eprintln!("Failed to load subject public key: {}", err);
-                return Err(())
+                return Err(Error::Key(format!(
+                    "Failed to load subject public key: {}", err
+                )))
             }
         };
+        if subject_key.algorithm() != PublicKeyFormat::EcdsaP256 {
+            return Err(Error::Key(
+                "Router keys must be ECDSA P-256 keys.".into()
+            ))
+        }
 
-        let not_before = self.not_before.unwrap_or_else(Time::now);
-        let validity = if let Some(not_after) = self.not_after {
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
             Validity::new(not_before, not_after)
         }
         else if let Some(valid_days) = self.valid_days {
-            Validity::new(not_before, not_before + Duration::days(valid_days))
+            Validity::new(not_before, not_before + valid_days.duration())
         }
         else {
-            eprintln!("Either --not-after or --days must be given.");
-            return Err(())
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
         };
 
         let mut cert = TbsCert::new(
             self.serial,
             issuer_pub.to_subject_name(),
             validity,
-            None,
+            Some(router_subject_name(first_asn, self.router_id)),
             subject_key,
-            KeyUsage::Ca,
-            if self.trim_resources { Overclaim::Trim }
-            else { Overclaim::Refuse }
+            KeyUsage::Ee,
+            Overclaim::Refuse,
         );
-        cert.set_basic_ca(Some(true));
         cert.set_authority_key_identifier(Some(issuer_pub.key_identifier()));
+        cert.set_extended_key_usage(Some(ExtendedKeyUsage::create_router()));
         cert.set_crl_uri(Some(self.crl_uri));
         cert.set_ca_issuer(Some(self.ca_issuer));
-        cert.set_ca_repository(Some(self.ca_repository));
-        cert.set_rpki_manifest(Some(self.rpki_manifest));
-        if let Some(rpki_notify) = self.rpki_notify {
-            cert.set_rpki_notify(Some(rpki_notify));
-        }
-        if self.inherit_v4 {
-            cert.set_v4_resources_inherit()
-        }
-        else if !self.v4_resources.is_empty() {
-            cert.v4_resources_from_iter(self.v4_resources)
-        }
-        if self.inherit_v6 {
-            cert.set_v6_resources_inherit()
-        }
-        else if !self.v6_resources.is_empty() {
-            cert.v6_resources_from_iter(self.v6_resources)
-        }
-        if self.inherit_as {
-            cert.set_as_resources_inherit()
-        }
-        else if !self.as_resources.is_empty() {
-            cert.as_resources_from_iter(self.as_resources)
-        }
+        cert.as_resources_from_iter(self.asns.into_iter().map(AsBlock::from));
 
-        let cert = unwrap!(cert.into_cert(&signer, &issuer_key)).to_captured();
+        let cert = cert.into_cert(&signer, &issuer_key)?.to_captured();
         save_file(&self.output, &cert)?;
         eprintln!("Cer: {}", self.output.display());
         Ok(())
     }
 }
 
+/// Creates the subject name for a router certificate.
+///
+/// Following RFC 8209, the common name is `ROUTER-` followed by the AS
+/// number in hex and the router ID, if given, goes into the serial number.
+fn router_subject_name(asn: Asn, router_id: Option<Ipv4Addr>) -> Name {
+    let cn = format!("ROUTER-{:08X}", asn.into_u32());
+    let sn = router_id.map(|id| format!("{:08X}", u32::from(id)));
+    let name = encode::sequence((
+        encode::set(
+            encode::sequence((
+                oid::AT_COMMON_NAME.encode(),
+                cn.as_bytes().encode_as(Tag::PRINTABLE_STRING),
+            ))
+        ),
+        sn.as_ref().map(|sn| {
+            encode::set(
+                encode::sequence((
+                    oid::AT_SERIAL_NUMBER.encode(),
+                    sn.as_bytes().encode_as(Tag::PRINTABLE_STRING),
+                ))
+            )
+        })
+    )).to_captured(Mode::Der);
+    unwrap!(Mode::Der.decode(name.as_slice(), Name::take_from))
+}
+
 
 //------------ Crl -----------------------------------------------------------
 
@@ -434,24 +989,40 @@ struct Crl {
     issuer_key: PathBuf,
 
     /// Time of this update. Defaults to now.
-    #[structopt(long = "this-update")]
-    this_update: Option<Time>,
+    #[structopt(long="this-update", raw(allow_hyphen_values="true"))]
+    this_update: Option<TimeSpec>,
 
     /// Time of the next update.
-    #[structopt(long = "next-update")]
-    next_update: Option<Time>,
+    #[structopt(long="next-update", raw(allow_hyphen_values="true"))]
+    next_update: Option<TimeSpec>,
 
-    /// The number of days until the next update.
+    /// Time until the next update, in days if just a number.
     #[structopt(long="next-days")]
-    next_days: Option<i64>,
+    next_days: Option<Period>,
 
     /// Revoked certificates.
     #[structopt(short = "c", long = "cert")]
     revoked_certs: Vec<CrlEntry>,
 
-    /// CRL number.
+    /// Serial numbers of certificates to revoke and record in the state.
+    #[structopt(long = "revoke")]
+    revoke: Vec<Serial>,
+
+    /// CRL number. Taken from the state if missing.
     #[structopt(long = "crl")]
-    crl_number: Serial,
+    crl_number: Option<Serial>,
+
+    /// Path to the directory with the state of the issuing CA.
+    #[structopt(long="state")]
+    state: Option<PathBuf>,
+
+    /// Create the CRL with the given defect.
+    #[structopt(long="corrupt")]
+    corrupt: Option<Defect>,
+
+    /// The format of the CRL: 'der' (default) or 'pem'.
+    #[structopt(long="format", default_value="der")]
+    format: Format,
 
     /// Path to file to write the CRL into.
     #[structopt(long="output")]
@@ -459,35 +1030,59 @@ struct Crl {
 }
 
 impl Crl {
-    pub fn run(self) -> Result<(), ()> {
+    pub fn run(self) -> Result<(), Error> {
+        Defect::check(self.corrupt, "crl", &[
+            Defect::BadSignature, Defect::WrongAki, Defect::Stale,
+        ])?;
         let (signer, issuer_key) = create_signer(&self.issuer_key)?;
-        let issuer_pub = unwrap!(signer.get_key_info(&issuer_key));
 
-        let this_update = self.this_update.unwrap_or_else(Time::now);
-        let next_update = if let Some(next_update) = self.next_update {
+        let this_update = clock::or_now(self.this_update)?;
+        let next_update = clock::resolve(self.next_update)?;
+        let next_update = if let Some(next_update) = next_update {
             next_update
         }
         else if let Some(days) = self.next_days {
-            this_update + Duration::days(days)
+            this_update + days.duration()
         }
         else {
-            eprintln!("Either --next-update or --next-days must be given.");
-            return Err(())
+            return Err(Error::Usage(
+                "Either --next-update or --next-days must be given.".into()
+            ))
+        };
+        let (this_update, next_update) = match self.corrupt {
+            Some(Defect::Stale) => corrupt::stale_updates(),
+            _ => (this_update, next_update)
         };
 
-        let crl = TbsCertList::new(
-            RpkiSignatureAlgorithm::default(),
-            issuer_pub.to_subject_name(),
-            this_update,
-            next_update,
-            self.revoked_certs,
-            issuer_pub.key_identifier(),
-            self.crl_number
-        );
+        let mut state = CaState::load_opt(self.state.as_deref())?;
+        let crl_number = take_counter(
+            state.as_mut(), Counter::CrlNumber, self.crl_number
+        )?;
+        let mut crl = CrlBuilder::new(this_update, next_update, crl_number);
+        crl.extend(self.revoked_certs);
+        if let Some(ref mut state) = state {
+            for serial in self.revoke {
+                state.revoke(serial, this_update);
+            }
+            crl.extend(state.crl_entries()?);
+        }
+        else if !self.revoke.is_empty() {
+            return Err(Error::Usage("--revoke requires --state.".into()))
+        }
+        if self.corrupt == Some(Defect::WrongAki) {
+            let issuer_pub = signer.get_key_info(&issuer_key)?;
+            crl.set_authority_key_identifier(
+                corrupt::wrong_key_identifier(issuer_pub.key_identifier())
+            );
+        }
 
-        let crl = unwrap!(crl.into_crl(&signer, &issuer_key)).to_captured();
-        save_file(&self.output, &crl)?;
+        let crl = crl.build(&signer, &issuer_key)?;
+        let crl = corrupt::apply_bad_signature(self.corrupt, crl);
+        pem::save(&self.output, self.format, pem::CRL, &crl)?;
         eprintln!("Crl: {}", self.output.display());
+        if let Some(state) = state {
+            state.save()?;
+        }
         Ok(())
     }
 }
@@ -501,33 +1096,38 @@ struct Roa {
     #[structopt(long="issuer-key")]
     issuer_key: PathBuf,
 
-    /// Serial number of the certificate.
+    /// Path to the certificate of the issuer to derive URIs from. Must have
+    /// the file name the certificate is published under.
+    #[structopt(long="issuer-cert")]
+    issuer_cert: Option<PathBuf>,
+
+    /// Serial number of the certificate. Taken from the state if missing.
     #[structopt(long="serial")]
-    serial: Serial,
+    serial: Option<Serial>,
 
     /// Not-before date of the certificate. Defaults to now.
-    #[structopt(long="not-before")]
-    not_before: Option<Time>,
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
 
     /// Not-after date of the certificate.
-    #[structopt(long="not-after")]
-    not_after: Option<Time>,
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
 
-    /// Duration of validity of certificate in days.
+    /// Duration of validity of certificate, in days if just a number.
     #[structopt(long="days")]
-    valid_days: Option<i64>,
+    valid_days: Option<Period>,
 
-    /// RPKI URI of the CRL.
+    /// RPKI URI of the CRL. Derived from the issuer certificate if missing.
     #[structopt(long="crl")]
-    crl_uri: uri::Rsync,
+    crl_uri: Option<uri::Rsync>,
 
-    /// CA issuer URI.
+    /// CA issuer URI. Derived from the issuer certificate if missing.
     #[structopt(long="ca-issuer")]
-    ca_issuer: uri::Rsync,
+    ca_issuer: Option<uri::Rsync>,
 
-    /// Signed Object URI
+    /// Signed Object URI. Derived from the issuer certificate if missing.
     #[structopt(long="signed-object")]
-    signed_object: uri::Rsync,
+    signed_object: Option<uri::Rsync>,
 
     /// The AS number for the ROA.
     #[structopt(long="asn")]
@@ -537,50 +1137,112 @@ struct Roa {
     #[structopt(long="prefixes")]
     prefixes: Vec<RoaPrefix>,
 
+    /// Leave out prefixes not covered by the issuer certificate.
+    #[structopt(long="trim-overclaim")]
+    trim_overclaim: bool,
+
+    /// Keep prefixes not covered by the issuer certificate.
+    #[structopt(long="allow-overclaim")]
+    allow_overclaim: bool,
+
+    /// Path to the directory with the state of the issuing CA.
+    #[structopt(long="state")]
+    state: Option<PathBuf>,
+
+    /// Create the ROA with the given defect.
+    #[structopt(long="corrupt")]
+    corrupt: Option<Defect>,
+
     /// Path to file to write the certificate into.
     #[structopt(long="output")]
     output: PathBuf
 }
 
 impl Roa {
-    pub fn run(self) -> Result<(), ()> {
-        let (mut v4, mut v6) = (Vec::new(), Vec::new());
-        for prefix in self.prefixes {
-            if prefix.v4 {
-                v4.push(prefix.prefix)
-            }
-            else {
-                v6.push(prefix.prefix)
-            }
-        }
+    pub fn run(self) -> Result<(), Error> {
+        Defect::check(self.corrupt, "roa", &[
+            Defect::BadSignature, Defect::WrongAki, Defect::MissingSia,
+            Defect::Expired, Defect::Overclaim,
+        ])?;
         let (signer, issuer_key) = create_signer(&self.issuer_key)?;
-
-        let not_before = self.not_before.unwrap_or_else(Time::now);
-        let validity = if let Some(not_after) = self.not_after {
+        let issuer_cert = IssuerCert::load_opt(self.issuer_cert.as_deref())?;
+        if let Some(issuer_cert) = issuer_cert.as_ref() {
+            issuer_cert.check_key(&signer.get_key_info(&issuer_key)?)?;
+        }
+        let crl_uri = resolve_uri(
+            self.crl_uri, issuer_cert.as_ref(), "--crl", IssuerCert::crl_uri
+        )?;
+        let ca_issuer = resolve_uri(
+            self.ca_issuer, issuer_cert.as_ref(), "--ca-issuer",
+            IssuerCert::ca_issuer
+        )?;
+        let output = &self.output;
+        let signed_object = resolve_uri(
+            self.signed_object, issuer_cert.as_ref(), "--signed-object",
+            |issuer| issuer.signed_object(output)
+        )?;
+
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
             Validity::new(not_before, not_after)
         }
         else if let Some(valid_days) = self.valid_days {
-            Validity::new(not_before, not_before + Duration::days(valid_days))
+            Validity::new(not_before, not_before + valid_days.duration())
         }
         else {
-            eprintln!("Either --not-after or --days must be given.");
-            return Err(())
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
         };
+        let mut state = CaState::load_opt(self.state.as_deref())?;
+        let serial = take_counter(
+            state.as_mut(), Counter::Serial, self.serial
+        )?;
+
+        let mut prefixes = self.prefixes;
+        if let Some(ref issuer_cert) = issuer_cert {
+            let containment = Containment::from_options(
+                self.trim_overclaim, self.allow_overclaim
+            );
+            let mut v4 = RoaPrefix::resources(&prefixes, true);
+            let mut v6 = RoaPrefix::resources(&prefixes, false);
+            issuer_cert.contain(
+                &mut v4, &mut v6, &mut AsResources::missing(), containment
+            )?;
+            if containment == Containment::Trim {
+                prefixes.retain(|prefix| {
+                    prefix.is_covered(if prefix.v4 { &v4 } else { &v6 })
+                });
+            }
+        }
 
         let mut roa = RoaBuilder::new(self.asn);
-        roa.extend_v4_from_slice(&v4);
-        roa.extend_v6_from_slice(&v6);
+        for prefix in prefixes {
+            if prefix.v4 {
+                roa.push_v4(prefix.prefix)
+            }
+            else {
+                roa.push_v6(prefix.prefix)
+            }
+        }
 
-        let roa = unwrap!(roa.finalize(
-            SignedObjectBuilder::new(
-                self.serial, validity, self.crl_uri, self.ca_issuer,
-                self.signed_object
-            ),
-            &signer, &issuer_key
-        ));
-        let roa = roa.to_captured();
+        let mut sigobj = corrupt::sigobj_builder(
+            self.corrupt, serial, validity, crl_uri, ca_issuer, signed_object,
+            signer.get_key_info(&issuer_key)?.key_identifier()
+        );
+        if self.corrupt == Some(Defect::Overclaim) {
+            sigobj.set_v4_resources(corrupt::all_v4_resources());
+            sigobj.set_v6_resources(corrupt::all_v6_resources());
+        }
+        let roa = roa.build(sigobj, &signer, &issuer_key)?;
+        let roa = corrupt::apply_bad_signature(self.corrupt, roa);
         save_file(&self.output, &roa)?;
         eprintln!("Roa: {}", self.output.display());
+        if let Some(mut state) = state {
+            state.issue(serial, "roa", &self.output, validity.not_after());
+            state.save()?;
+        }
         Ok(())
     }
 }
@@ -594,6 +1256,28 @@ struct RoaPrefix {
     prefix: RoaIpAddress,
 }
 
+impl RoaPrefix {
+    /// Returns the resources covered by the prefixes of one family.
+    fn resources(prefixes: &[Self], v4: bool) -> IpResources {
+        IpResources::blocks(
+            prefixes.iter().filter(|prefix| prefix.v4 == v4).map(|prefix| {
+                IpBlock::from(prefix.prefix.prefix())
+            }).collect()
+        )
+    }
+
+    /// Returns whether the prefix is completely covered by the resources.
+    ///
+    /// A prefix only partly covered is not covered at all since it can’t
+    /// be split without changing the meaning of its maximum length.
+    fn is_covered(&self, resources: &IpResources) -> bool {
+        match resources.to_blocks() {
+            Ok(blocks) => blocks.contains_roa(&self.prefix),
+            Err(_) => false
+        }
+    }
+}
+
 impl FromStr for RoaPrefix {
     type Err = String;
 
@@ -637,193 +1321,1065 @@ struct Mft {
     #[structopt(long="issuer-key")]
     issuer_key: PathBuf,
 
-    /// Serial number of the certificate.
+    /// Path to the certificate of the issuer to derive URIs from. Must have
+    /// the file name the certificate is published under.
+    #[structopt(long="issuer-cert")]
+    issuer_cert: Option<PathBuf>,
+
+    /// Serial number of the certificate. Taken from the state if missing.
     #[structopt(long="serial")]
-    serial: Serial,
+    serial: Option<Serial>,
 
     /// Not-before date of the certificate. Defaults to now.
-    #[structopt(long="not-before")]
-    not_before: Option<Time>,
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
 
     /// Not-after date of the certificate.
-    #[structopt(long="not-after")]
-    not_after: Option<Time>,
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
 
-    /// Duration of validity of certificate in days.
+    /// Duration of validity of certificate, in days if just a number.
     #[structopt(long="days")]
-    valid_days: Option<i64>,
+    valid_days: Option<Period>,
 
-    /// RPKI URI of the CRL.
+    /// RPKI URI of the CRL. Derived from the issuer certificate if missing.
     #[structopt(long="crl")]
-    crl_uri: uri::Rsync,
+    crl_uri: Option<uri::Rsync>,
 
-    /// CA issuer URI.
+    /// CA issuer URI. Derived from the issuer certificate if missing.
     #[structopt(long="ca-issuer")]
-    ca_issuer: uri::Rsync,
+    ca_issuer: Option<uri::Rsync>,
 
-    /// The number of this manifest.
+    /// The number of this manifest. Taken from the state if missing.
     #[structopt(long="number")]
-    number: Serial,
+    number: Option<Serial>,
 
-    /// Signed Object URI
+    /// Signed Object URI. Derived from the issuer certificate if missing.
     #[structopt(long="signed-object")]
-    signed_object: uri::Rsync,
+    signed_object: Option<uri::Rsync>,
 
     /// The update time of this manifest.
-    #[structopt(long="this-update")]
-    this_update: Option<Time>,
+    #[structopt(long="this-update", raw(allow_hyphen_values="true"))]
+    this_update: Option<TimeSpec>,
 
     /// The update time of the next manifest.
-    #[structopt(long="next-update")]
-    next_update: Option<Time>,
+    #[structopt(long="next-update", raw(allow_hyphen_values="true"))]
+    next_update: Option<TimeSpec>,
 
-    /// The number of days until the next update.
+    /// Time until the next update, in days if just a number.
     #[structopt(long="next-days")]
-    next_days: Option<i64>,
+    next_days: Option<Period>,
 
     /// The files to include in the manifest
     #[structopt(long="files")]
     files: Vec<PathBuf>,
 
+    /// Include all publishable objects in this directory.
+    #[structopt(long="dir")]
+    dir: Option<PathBuf>,
+
+    /// Leave out files from --dir whose name matches this pattern.
+    #[structopt(long="exclude")]
+    exclude: Vec<Glob>,
+
+    /// Path to the directory with the state of the issuing CA.
+    #[structopt(long="state")]
+    state: Option<PathBuf>,
+
+    /// Create the manifest with the given defect.
+    #[structopt(long="corrupt")]
+    corrupt: Option<Defect>,
+
     /// Path to file to write the certificate into.
     #[structopt(long="output")]
     output: PathBuf,
 }
 
 impl Mft {
-    pub fn run(self) -> Result<(), ()> {
+    pub fn run(self) -> Result<(), Error> {
+        Defect::check(self.corrupt, "mft", &[
+            Defect::BadSignature, Defect::WrongAki, Defect::MissingSia,
+            Defect::Expired, Defect::Stale, Defect::HashMismatch,
+        ])?;
+        let mut files = self.files.clone();
+        if let Some(ref dir) = self.dir {
+            files.extend(publishable_files(dir, &self.exclude, &self.output)?);
+        }
+        else if !self.exclude.is_empty() {
+            return Err(Error::Usage("--exclude requires --dir.".into()))
+        }
+        if self.corrupt == Some(Defect::HashMismatch) && files.is_empty() {
+            return Err(Error::Usage(
+                "Defect 'hash-mismatch' requires at least one file.".into()
+            ))
+        }
         let (signer, issuer_key) = create_signer(&self.issuer_key)?;
-
-        let not_before = self.not_before.unwrap_or_else(Time::now);
-        let validity = if let Some(not_after) = self.not_after {
+        let issuer_cert = IssuerCert::load_opt(self.issuer_cert.as_deref())?;
+        if let Some(issuer_cert) = issuer_cert.as_ref() {
+            issuer_cert.check_key(&signer.get_key_info(&issuer_key)?)?;
+        }
+        let crl_uri = resolve_uri(
+            self.crl_uri, issuer_cert.as_ref(), "--crl", IssuerCert::crl_uri
+        )?;
+        let ca_issuer = resolve_uri(
+            self.ca_issuer, issuer_cert.as_ref(), "--ca-issuer",
+            IssuerCert::ca_issuer
+        )?;
+        let signed_object = resolve_uri(
+            self.signed_object, issuer_cert.as_ref(), "--signed-object",
+            IssuerCert::rpki_manifest
+        )?;
+
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
             Validity::new(not_before, not_after)
         }
         else if let Some(valid_days) = self.valid_days {
-            Validity::new(not_before, not_before + Duration::days(valid_days))
+            Validity::new(not_before, not_before + valid_days.duration())
         }
         else {
-            eprintln!("Either --not-after or --days must be given.");
-            return Err(())
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
         };
-        let this_update = self.this_update.unwrap_or_else(Time::now);
-        let next_update = if let Some(next_update) = self.next_update {
+        let mut state = CaState::load_opt(self.state.as_deref())?;
+        let serial = take_counter(
+            state.as_mut(), Counter::Serial, self.serial
+        )?;
+        let number = take_counter(
+            state.as_mut(), Counter::ManifestNumber, self.number
+        )?;
+        let this_update = clock::or_now(self.this_update)?;
+        let next_update = clock::resolve(self.next_update)?;
+        let next_update = if let Some(next_update) = next_update {
             next_update
         }
         else if let Some(days) = self.next_days {
-            this_update + Duration::days(days)
+            this_update + days.duration()
         }
         else {
-            eprintln!("Either --next-update or --next-days must be given.");
-            return Err(())
+            return Err(Error::Usage(
+                "Either --next-update or --next-days must be given.".into()
+            ))
         };
 
-        let alg = DigestAlgorithm::default();
-        let mut files = Vec::new();
-        for path in self.files {
-            let mut file = match File::open(&path) {
-                Ok(file) => file,
-                Err(err) => {
-                    eprintln!("Cannot open file {}: {}", path.display(), err);
-                    return Err(())
-                }
-            };
-            let name = match path.file_name().and_then(OsStr::to_str) {
-                Some(name) if name.is_ascii() => name.to_string(),
-                _ => {
-                    eprintln!("Illegal file name {}.", path.display());
-                    return Err(())
-                }
-            };
-            let mut digest = alg.start();
-            let mut buf = [0u8; 4096];
-            loop {
-                let read = match file.read(&mut buf) {
-                    Ok(read) => read,
-                    Err(err) => {
-                        eprintln!(
-                            "Cannot read file {}: {}", path.display(), err
-                        );
-                        return Err(())
-                    }
-                };
-                if read == 0 {
-                    break;
-                }
-                digest.update(&buf[..read]);
+        let (this_update, next_update) = match self.corrupt {
+            Some(Defect::Stale) => corrupt::stale_updates(),
+            _ => (this_update, next_update)
+        };
+
+        let mut manifest = ManifestBuilder::new(
+            number, this_update, next_update
+        );
+        let alg = manifest.digest_algorithm();
+        for (index, path) in files.iter().enumerate() {
+            let (name, digest) = digest_file(alg, path)?;
+            let mut digest = Bytes::copy_from_slice(digest.as_ref());
+            if self.corrupt == Some(Defect::HashMismatch) && index == 0 {
+                digest = corrupt::wrong_hash(&digest);
             }
-            files.push(FileAndHash::new(name, digest.finish()));
+            manifest.push(name.into(), digest);
         }
 
-        let content = ManifestContent::new(
-            self.number, this_update, next_update, alg, files
+        let sigobj = corrupt::sigobj_builder(
+            self.corrupt, serial, validity, crl_uri, ca_issuer, signed_object,
+            signer.get_key_info(&issuer_key)?.key_identifier()
         );
+        let manifest = manifest.build(sigobj, &signer, &issuer_key)?;
+        let manifest = corrupt::apply_bad_signature(self.corrupt, manifest);
+        save_file(&self.output, &manifest)?;
+        eprintln!("Mft: {}", self.output.display());
+        if let Some(mut state) = state {
+            state.issue(serial, "mft", &self.output, validity.not_after());
+            state.save()?;
+        }
+        Ok(())
+    }
+}
+
+
+//------------ Aspa ----------------------------------------------------------
+
+#[derive(StructOpt)]
+struct Aspa {
+    /// Path to the private key of the certificate issuer.
+    #[structopt(long="issuer-key")]
+    issuer_key: PathBuf,
+
+    /// Serial number of the certificate.
+    #[structopt(long="serial")]
+    serial: Serial,
+
+    /// Not-before date of the certificate. Defaults to now.
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
+
+    /// Not-after date of the certificate.
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
+
+    /// Duration of validity of certificate, in days if just a number.
+    #[structopt(long="days")]
+    valid_days: Option<Period>,
+
+    /// RPKI URI of the CRL.
+    #[structopt(long="crl")]
+    crl_uri: uri::Rsync,
+
+    /// CA issuer URI.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: uri::Rsync,
+
+    /// Signed Object URI
+    #[structopt(long="signed-object")]
+    signed_object: uri::Rsync,
+
+    /// The customer AS number for the ASPA.
+    #[structopt(long="customer")]
+    customer: Asn,
+
+    /// The provider AS numbers, optionally limited via '(v4)' or '(v6)'.
+    #[structopt(long="providers")]
+    providers: Vec<ProviderAs>,
+
+    /// Path to file to write the ASPA into.
+    #[structopt(long="output")]
+    output: PathBuf
+}
+
+impl Aspa {
+    pub fn run(self) -> Result<(), Error> {
+        let aspa = match AspaBuilder::new(self.customer, self.providers) {
+            Ok(aspa) => aspa,
+            Err(err) => {
+                return Err(Error::Usage(format!(
+                    "Invalid provider ASNs: {}", err
+                )))
+            }
+        };
+        let (signer, issuer_key) = create_signer(&self.issuer_key)?;
 
-        let manifest = unwrap!(content.into_manifest(
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
+            Validity::new(not_before, not_after)
+        }
+        else if let Some(valid_days) = self.valid_days {
+            Validity::new(not_before, not_before + valid_days.duration())
+        }
+        else {
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
+        };
+
+        let aspa = aspa.finalize(
             SignedObjectBuilder::new(
                 self.serial, validity, self.crl_uri, self.ca_issuer,
                 self.signed_object
             ),
             &signer, &issuer_key
-        ));
-        let manifest = manifest.to_captured();
-        save_file(&self.output, &manifest)?;
-        eprintln!("Mft: {}", self.output.display());
+        )?;
+        let aspa = aspa.to_captured();
+        save_file(&self.output, &aspa)?;
+        eprintln!("Asa: {}", self.output.display());
+        Ok(())
+    }
+}
+
+
+//------------ Gbr -----------------------------------------------------------
+
+#[derive(StructOpt)]
+struct Gbr {
+    /// Path to the private key of the certificate issuer.
+    #[structopt(long="issuer-key")]
+    issuer_key: PathBuf,
+
+    /// Serial number of the certificate.
+    #[structopt(long="serial")]
+    serial: Serial,
+
+    /// Not-before date of the certificate. Defaults to now.
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
+
+    /// Not-after date of the certificate.
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
+
+    /// Duration of validity of certificate, in days if just a number.
+    #[structopt(long="days")]
+    valid_days: Option<Period>,
+
+    /// RPKI URI of the CRL.
+    #[structopt(long="crl")]
+    crl_uri: uri::Rsync,
+
+    /// CA issuer URI.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: uri::Rsync,
+
+    /// Signed Object URI
+    #[structopt(long="signed-object")]
+    signed_object: uri::Rsync,
+
+    /// Path to a file with a complete vCard. Overrides all other fields.
+    #[structopt(long="vcard")]
+    vcard: Option<PathBuf>,
+
+    /// The formatted name of the contact.
+    #[structopt(long="fn")]
+    full_name: Option<String>,
+
+    /// The organization of the contact.
+    #[structopt(long="org")]
+    org: Option<String>,
+
+    /// The address of the contact in vCard ADR format.
+    #[structopt(long="adr")]
+    adr: Option<String>,
+
+    /// The telephone number of the contact.
+    #[structopt(long="tel")]
+    tel: Option<String>,
+
+    /// The email address of the contact.
+    #[structopt(long="email")]
+    email: Option<String>,
+
+    /// Path to file to write the Ghostbusters record into.
+    #[structopt(long="output")]
+    output: PathBuf
+}
+
+impl Gbr {
+    pub fn run(self) -> Result<(), Error> {
+        let vcard = match self.vcard {
+            Some(ref path) => load_file(path)?,
+            None => self.make_vcard()?.into_bytes(),
+        };
+        if let Err(err) = check_vcard(&vcard) {
+            return Err(Error::Encoding(format!("Invalid vCard: {}", err)))
+        }
+        let (signer, issuer_key) = create_signer(&self.issuer_key)?;
+
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
+            Validity::new(not_before, not_after)
+        }
+        else if let Some(valid_days) = self.valid_days {
+            Validity::new(not_before, not_before + valid_days.duration())
+        }
+        else {
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
+        };
+
+        let mut sigobj = SignedObjectBuilder::new(
+            self.serial, validity, self.crl_uri, self.ca_issuer,
+            self.signed_object
+        );
+        sigobj.set_v4_resources_inherit();
+        sigobj.set_v6_resources_inherit();
+        sigobj.set_as_resources_inherit();
+        let gbr = sigobj.finalize(
+            Oid(CT_GHOSTBUSTERS.0.into()), Bytes::from(vcard),
+            &signer, &issuer_key
+        )?;
+        let gbr = gbr.encode_ref().to_captured(Mode::Der);
+        save_file(&self.output, &gbr)?;
+        eprintln!("Gbr: {}", self.output.display());
         Ok(())
     }
+
+    /// Assembles a vCard from the individual fields.
+    fn make_vcard(&self) -> Result<String, Error> {
+        let full_name = match self.full_name {
+            Some(ref full_name) => full_name,
+            None => {
+                return Err(Error::Usage(
+                    "Either --vcard or --fn must be given.".into()
+                ))
+            }
+        };
+        let mut res = String::from("BEGIN:VCARD\r\nVERSION:4.0\r\n");
+        unwrap!(write!(res, "FN:{}\r\n", escape_vcard_text(full_name)));
+        if let Some(ref org) = self.org {
+            unwrap!(write!(res, "ORG:{}\r\n", escape_vcard_text(org)));
+        }
+        if let Some(ref adr) = self.adr {
+            unwrap!(write!(res, "ADR:{}\r\n", adr));
+        }
+        if let Some(ref tel) = self.tel {
+            if tel.starts_with("tel:") {
+                unwrap!(write!(res, "TEL;VALUE=uri:{}\r\n", tel));
+            }
+            else {
+                unwrap!(write!(res, "TEL;VALUE=uri:tel:{}\r\n", tel));
+            }
+        }
+        if let Some(ref email) = self.email {
+            unwrap!(write!(res, "EMAIL:{}\r\n", email));
+        }
+        res.push_str("END:VCARD\r\n");
+        Ok(res)
+    }
+}
+
+/// The content type of a Ghostbusters record.
+///
+/// This is `id-ct-rpkiGhostbusters` from RFC 6493.
+const CT_GHOSTBUSTERS: ConstOid
+    = Oid(&[42, 134, 72, 134, 247, 13, 1, 9, 16, 1, 35]);
+
+/// Escapes a vCard text value.
+fn escape_vcard_text(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '\\' | ',' | ';' => {
+                res.push('\\');
+                res.push(ch);
+            }
+            '\n' => res.push_str("\\n"),
+            _ => res.push(ch)
+        }
+    }
+    res
+}
+
+/// Checks that a vCard follows the profile defined in RFC 6493.
+fn check_vcard(vcard: &[u8]) -> Result<(), String> {
+    let vcard = match std::str::from_utf8(vcard) {
+        Ok(vcard) => vcard,
+        Err(_) => return Err("not UTF-8 encoded".into())
+    };
+
+    // Unfold the content lines first.
+    let vcard = vcard.replace("\r\n ", "").replace("\r\n\t", "");
+    let mut lines = vcard.split("\r\n").collect::<Vec<_>>();
+    if lines.last() == Some(&"") {
+        lines.pop();
+    }
+    if lines.first() != Some(&"BEGIN:VCARD") {
+        return Err("must start with BEGIN:VCARD".into())
+    }
+    if lines.get(1) != Some(&"VERSION:4.0") {
+        return Err("must continue with VERSION:4.0".into())
+    }
+    if lines.last() != Some(&"END:VCARD") || lines.len() < 3 {
+        return Err("must end with END:VCARD".into())
+    }
+
+    let mut full_name = 0;
+    let mut contact = false;
+    for line in &lines[2..lines.len() - 1] {
+        let name = match line.find([':', ';']) {
+            Some(idx) => &line[..idx],
+            None => return Err(format!("invalid content line '{}'", line))
+        };
+        match name.to_ascii_uppercase().as_str() {
+            "FN" => full_name += 1,
+            "ORG" => { }
+            "ADR" | "TEL" | "EMAIL" => contact = true,
+            _ => {
+                return Err(format!("property {} not allowed", name))
+            }
+        }
+    }
+    if full_name != 1 {
+        return Err("must contain exactly one FN property".into())
+    }
+    if !contact {
+        return Err("must contain at least one of ADR, TEL, or EMAIL".into())
+    }
+    Ok(())
+}
+
+
+//------------ Rsc -----------------------------------------------------------
+
+#[derive(StructOpt)]
+struct Rsc {
+    /// Path to the private key of the certificate issuer.
+    #[structopt(long="issuer-key")]
+    issuer_key: PathBuf,
+
+    /// Serial number of the certificate.
+    #[structopt(long="serial")]
+    serial: Serial,
+
+    /// Not-before date of the certificate. Defaults to now.
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
+
+    /// Not-after date of the certificate.
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
+
+    /// Duration of validity of certificate, in days if just a number.
+    #[structopt(long="days")]
+    valid_days: Option<Period>,
+
+    /// RPKI URI of the CRL.
+    #[structopt(long="crl")]
+    crl_uri: uri::Rsync,
+
+    /// CA issuer URI.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: uri::Rsync,
+
+    /// IPv4 resources.
+    #[structopt(long="v4")]
+    v4_resources: Vec<IpBlock>,
+
+    /// IPv6 resources.
+    #[structopt(long="v6")]
+    v6_resources: Vec<IpBlock>,
+
+    /// AS resources.
+    #[structopt(long="as")]
+    as_resources: Vec<AsBlock>,
+
+    /// The files to include in the checklist.
+    #[structopt(long="files")]
+    files: Vec<PathBuf>,
+
+    /// Path to file to write the checklist into.
+    #[structopt(long="output")]
+    output: PathBuf,
+}
+
+impl Rsc {
+    pub fn run(self) -> Result<(), Error> {
+        if self.v4_resources.is_empty() && self.v6_resources.is_empty()
+            && self.as_resources.is_empty()
+        {
+            return Err(Error::Usage(
+                "At least one of --v4, --v6, or --as must be given.".into()
+            ))
+        }
+        if self.files.is_empty() {
+            return Err(Error::Usage(
+                "At least one file must be given via --files.".into()
+            ))
+        }
+        let (signer, issuer_key) = create_signer(&self.issuer_key)?;
+
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
+            Validity::new(not_before, not_after)
+        }
+        else if let Some(valid_days) = self.valid_days {
+            Validity::new(not_before, not_before + valid_days.duration())
+        }
+        else {
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
+        };
+
+        let alg = DigestAlgorithm::default();
+        let mut files = Vec::new();
+        for path in &self.files {
+            let (name, digest) = digest_file(alg, path)?;
+            files.push(encode::sequence((
+                name.as_bytes().encode_as(Tag::IA5_STRING),
+                OctetString::encode_slice(digest.as_ref()),
+            )).to_captured(Mode::Der));
+        }
+
+        let v4: IpBlocks = self.v4_resources.into_iter().collect();
+        let v6: IpBlocks = self.v6_resources.into_iter().collect();
+        let asns: AsBlocks = self.as_resources.into_iter().collect();
+
+        // RpkiSignedChecklist ::= SEQUENCE {
+        //     version [0] INTEGER DEFAULT 0,
+        //     resources ResourceBlock,
+        //     digestAlgorithm DigestAlgorithmIdentifier,
+        //     checkList SEQUENCE SIZE (1..MAX) OF FileNameAndHash }
+        let content = encode::sequence((
+            encode::sequence((
+                if asns.is_empty() { None }
+                else {
+                    Some(encode::sequence_as(Tag::CTX_0,
+                        encode::sequence(
+                            encode::sequence_as(Tag::CTX_0,
+                                encode::sequence(asns.encode_ref())
+                            )
+                        )
+                    ))
+                },
+                if v4.is_empty() && v6.is_empty() { None }
+                else {
+                    Some(encode::sequence_as(Tag::CTX_1,
+                        encode::sequence((
+                            if v4.is_empty() { None }
+                            else {
+                                Some(v4.encode_family(AddressFamily::Ipv4))
+                            },
+                            if v6.is_empty() { None }
+                            else {
+                                Some(v6.encode_family(AddressFamily::Ipv6))
+                            },
+                        ))
+                    ))
+                },
+            )),
+            alg.encode(),
+            encode::sequence(encode::iter(files.iter())),
+        )).to_captured(Mode::Der);
+
+        let mut sigobj = sigobj::SignedObjectBuilder::new(
+            self.serial, validity, self.crl_uri, self.ca_issuer, None
+        );
+        sigobj.set_v4_resources(IpResources::blocks(v4.clone()));
+        sigobj.set_v6_resources(IpResources::blocks(v6.clone()));
+        sigobj.set_as_resources(AsResources::blocks(asns.clone()));
+        let rsc = sigobj.finalize(
+            CT_SIGNED_CHECKLIST, content.as_slice(), &signer, &issuer_key
+        )?;
+        save_file(&self.output, &rsc)?;
+        eprintln!("Rsc: {}", self.output.display());
+        Ok(())
+    }
+}
+
+/// The content type of an RPKI Signed Checklist.
+///
+/// This is `id-ct-signedChecklist` from RFC 9323.
+const CT_SIGNED_CHECKLIST: ConstOid
+    = Oid(&[42, 134, 72, 134, 247, 13, 1, 9, 16, 1, 48]);
+
+
+//------------ Tak -----------------------------------------------------------
+
+#[derive(StructOpt)]
+struct Tak {
+    /// Path to the private key of the current trust anchor.
+    #[structopt(long="key")]
+    key: PathBuf,
+
+    /// Serial number of the certificate.
+    #[structopt(long="serial")]
+    serial: Serial,
+
+    /// Not-before date of the certificate. Defaults to now.
+    #[structopt(long="not-before", raw(allow_hyphen_values="true"))]
+    not_before: Option<TimeSpec>,
+
+    /// Not-after date of the certificate.
+    #[structopt(long="not-after", raw(allow_hyphen_values="true"))]
+    not_after: Option<TimeSpec>,
+
+    /// Duration of validity of certificate, in days if just a number.
+    #[structopt(long="days")]
+    valid_days: Option<Period>,
+
+    /// RPKI URI of the CRL.
+    #[structopt(long="crl")]
+    crl_uri: uri::Rsync,
+
+    /// CA issuer URI.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: uri::Rsync,
+
+    /// Signed Object URI
+    #[structopt(long="signed-object")]
+    signed_object: uri::Rsync,
+
+    /// Path to the TAL of the current key.
+    #[structopt(long="current-tal")]
+    current_tal: PathBuf,
+
+    /// Path to the TAL of the predecessor key.
+    #[structopt(long="predecessor-tal")]
+    predecessor_tal: Option<PathBuf>,
+
+    /// Path to the TAL of the successor key.
+    #[structopt(long="successor-tal")]
+    successor_tal: Option<PathBuf>,
+
+    /// Path to file to write the TAK object into.
+    #[structopt(long="output")]
+    output: PathBuf,
+}
+
+impl Tak {
+    pub fn run(self) -> Result<(), Error> {
+        let (signer, key) = create_signer(&self.key)?;
+        let key_pub = signer.get_key_info(&key)?;
+
+        let current = TalContent::load(&self.current_tal)?;
+        if current.key != key_pub {
+            return Err(Error::Key(format!(
+                "Key in {} does not match the key given via --key.",
+                self.current_tal.display()
+            )))
+        }
+        let predecessor = match self.predecessor_tal {
+            Some(ref path) => Some(TalContent::load(path)?),
+            None => None
+        };
+        let successor = match self.successor_tal {
+            Some(ref path) => Some(TalContent::load(path)?),
+            None => None
+        };
+
+        let not_before = clock::or_now(self.not_before)?;
+        let not_after = clock::resolve(self.not_after)?;
+        let validity = if let Some(not_after) = not_after {
+            Validity::new(not_before, not_after)
+        }
+        else if let Some(valid_days) = self.valid_days {
+            Validity::new(not_before, not_before + valid_days.duration())
+        }
+        else {
+            return Err(Error::Usage(
+                "Either --not-after or --days must be given.".into()
+            ))
+        };
+
+        // TAK ::= SEQUENCE {
+        //     version INTEGER DEFAULT 0,
+        //     current TAKey,
+        //     predecessor [0] TAKey OPTIONAL,
+        //     successor [1] TAKey OPTIONAL }
+        let content = encode::sequence((
+            current.encode_tak_key(),
+            predecessor.as_ref().map(|tal| {
+                encode::sequence_as(Tag::CTX_0, tal.encode_tak_key())
+            }),
+            successor.as_ref().map(|tal| {
+                encode::sequence_as(Tag::CTX_1, tal.encode_tak_key())
+            }),
+        )).to_captured(Mode::Der);
+
+        let mut sigobj = SignedObjectBuilder::new(
+            self.serial, validity, self.crl_uri, self.ca_issuer,
+            self.signed_object
+        );
+        sigobj.set_v4_resources_inherit();
+        sigobj.set_v6_resources_inherit();
+        sigobj.set_as_resources_inherit();
+        let tak = sigobj.finalize(
+            Oid(CT_TAK.0.into()), content.into_bytes(), &signer, &key
+        )?;
+        let tak = tak.encode_ref().to_captured(Mode::Der);
+        save_file(&self.output, &tak)?;
+        eprintln!("Tak: {}", self.output.display());
+        Ok(())
+    }
+}
+
+/// The content type of a Trust Anchor Key object.
+///
+/// This is `id-ct-SignedTAL` from RFC 9691.
+const CT_TAK: ConstOid
+    = Oid(&[42, 134, 72, 134, 247, 13, 1, 9, 16, 1, 50]);
+
+
+//------------ TalContent ----------------------------------------------------
+
+/// The content of a trust anchor locator.
+///
+/// This is also what makes up a key in a TAK object.
+struct TalContent {
+    /// The comments of the TAL without the leading hash.
+    comments: Vec<String>,
+
+    /// The URIs of the trust anchor certificate.
+    uris: Vec<String>,
+
+    /// The public key of the trust anchor.
+    key: PublicKey,
+}
+
+impl TalContent {
+    /// Loads the content from a TAL file.
+    fn load(path: &Path) -> Result<Self, Error> {
+        let text = load_file(path)?;
+        let text = match String::from_utf8(text) {
+            Ok(text) => text,
+            Err(_) => {
+                return Err(Error::Encoding(format!(
+                    "Invalid TAL {}: not UTF-8.", path.display()
+                )))
+            }
+        };
+        Self::from_text(&text).map_err(|err| {
+            Error::Encoding(format!("Invalid TAL {}: {}", path.display(), err))
+        })
+    }
+
+    /// Parses the content from the text of a TAL file.
+    fn from_text(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().map(str::trim_end).peekable();
+        let mut comments = Vec::new();
+        while let Some(line) = lines.next_if(|line| line.starts_with('#')) {
+            comments.push(line[1..].trim_start().to_string());
+        }
+        let mut uris = Vec::new();
+        for line in &mut lines {
+            if line.is_empty() {
+                break
+            }
+            uris.push(line.to_string());
+        }
+        if uris.is_empty() {
+            return Err("no URIs".into())
+        }
+        let key = lines.collect::<String>();
+        let key = match base64::decode(&key) {
+            Ok(key) => key,
+            Err(_) => return Err("invalid key encoding".into())
+        };
+        let key = match PublicKey::decode(key.as_slice()) {
+            Ok(key) => key,
+            Err(err) => return Err(format!("invalid key: {}", err))
+        };
+        Ok(TalContent { comments, uris, key })
+    }
+
+    /// Returns the text of a TAL file.
+    fn to_text(&self) -> String {
+        let mut res = String::new();
+        for comment in &self.comments {
+            unwrap!(writeln!(res, "# {}", comment));
+        }
+        for uri in &self.uris {
+            unwrap!(writeln!(res, "{}", uri));
+        }
+        unwrap!(writeln!(res));
+        unwrap!(
+            writeln!(res, "{}", base64::encode(&self.key.to_info_bytes()))
+        );
+        res
+    }
+
+    /// Returns an encoder for the content as a TAK key.
+    //
+    // TAKey ::= SEQUENCE {
+    //     comments SEQUENCE SIZE (0..MAX) OF UTF8String,
+    //     certificateURIs SEQUENCE SIZE (1..MAX) OF CertificateURI,
+    //     subjectPublicKeyInfo SubjectPublicKeyInfo }
+    fn encode_tak_key(&self) -> impl encode::Values + '_ {
+        encode::sequence((
+            encode::sequence(encode::iter(self.comments.iter().map(|item| {
+                item.as_bytes().encode_as(Tag::UTF8_STRING)
+            }))),
+            encode::sequence(encode::iter(self.uris.iter().map(|item| {
+                item.as_bytes().encode_as(Tag::IA5_STRING)
+            }))),
+            self.key.encode_ref(),
+        ))
+    }
 }
 
 
 //------------ Helpers -------------------------------------------------------
 
-fn create_signer(issuer_key: &Path) -> Result<(OpenSslSigner, KeyId), ()> {
+/// Creates the signer for a key given on the command line.
+///
+/// The key is either the path to a private key file or a PKCS#11 URI.
+fn create_signer(issuer_key: &Path) -> Result<(KeySigner, KeyRef), Error> {
+    if let Some(uri) = issuer_key.to_str().filter(|uri| {
+        Pkcs11Uri::is_pkcs11(uri)
+    }) {
+        let uri = match Pkcs11Uri::from_str(uri) {
+            Ok(uri) => uri,
+            Err(err) => {
+                return Err(Error::Key(format!(
+                    "Invalid PKCS#11 URI {}: {}", uri, err
+                )))
+            }
+        };
+        return match Pkcs11Signer::open(&uri) {
+            Ok((signer, key)) => {
+                Ok((KeySigner::Pkcs11(signer), KeyRef::Pkcs11(key)))
+            }
+            Err(err) => {
+                Err(Error::Key(format!("Failed to open PKCS#11 key: {}", err)))
+            }
+        }
+    }
     let signer = OpenSslSigner::new();
-    let der = load_file(issuer_key)?;
+    let der = pem::load_der(issuer_key)?;
     let key = match signer.key_from_der(&der) {
         Ok(key) => key,
         Err(err) => {
-            eprintln!(
-                "Invalid issuer key {}: {}",
-                issuer_key.display(), err
-            );
-            return Err(())
+            return Err(Error::Key(format!(
+                "Invalid issuer key {}: {}", issuer_key.display(), err
+            )))
+        }
+    };
+    Ok((KeySigner::OpenSsl(signer), KeyRef::OpenSsl(key)))
+}
+
+/// Converts IP blocks given on the command line into resources.
+fn ip_resources(blocks: Vec<IpBlock>, inherit: bool) -> IpResources {
+    if inherit {
+        IpResources::inherit()
+    }
+    else {
+        IpResources::blocks(blocks.into_iter().collect())
+    }
+}
+
+/// Converts AS blocks given on the command line into resources.
+fn as_resources(blocks: Vec<AsBlock>, inherit: bool) -> AsResources {
+    if inherit {
+        AsResources::inherit()
+    }
+    else {
+        AsResources::blocks(blocks.into_iter().collect())
+    }
+}
+
+/// The extensions of files published by a CA.
+const PUBLISHABLE: &[&str] = &[
+    "asa", "cer", "crl", "gbr", "roa", "sig", "tak"
+];
+
+/// Returns all publishable objects in a directory.
+///
+/// Only files directly in the directory with one of the extensions in
+/// `PUBLISHABLE` are included unless their name matches one of the
+/// patterns in `exclude`. The file `output` is never included. The files
+/// are sorted by name.
+fn publishable_files(
+    dir: &Path, exclude: &[Glob], output: &Path
+) -> Result<Vec<PathBuf>, Error> {
+    let entries = fs::read_dir(dir).map_err(|err| {
+        Error::Io(format!(
+            "Failed to read directory {}: {}", dir.display(), err
+        ))
+    })?;
+    let output = fs::canonicalize(output).ok();
+    let mut res = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|err| {
+            Error::Io(format!(
+                "Failed to read directory {}: {}", dir.display(), err
+            ))
+        })?.path();
+        if !path.is_file() {
+            continue
+        }
+        let name = match path.file_name().and_then(OsStr::to_str) {
+            Some(name) => name,
+            None => continue
+        };
+        let publishable = path.extension().and_then(OsStr::to_str).map(|ext| {
+            PUBLISHABLE.contains(&ext)
+        }).unwrap_or(false);
+        if !publishable || exclude.iter().any(|glob| glob.matches(name)) {
+            continue
+        }
+        if output.is_some() && fs::canonicalize(&path).ok() == output {
+            continue
+        }
+        res.push(path);
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Calculates the digest of a file.
+///
+/// Returns the file name portion of the path and the digest.
+fn digest_file(
+    alg: DigestAlgorithm, path: &Path
+) -> Result<(String, Digest), Error> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(Error::Io(format!(
+                "Cannot open file {}: {}", path.display(), err
+            )))
+        }
+    };
+    let name = match path.file_name().and_then(OsStr::to_str) {
+        Some(name) if name.is_ascii() => name.to_string(),
+        _ => {
+            return Err(Error::Usage(format!(
+                "Illegal file name {}.", path.display()
+            )))
         }
     };
-    Ok((signer, key))
+    let mut digest = alg.start();
+    let mut buf = [0u8; 4096];
+    loop {
+        let read = match file.read(&mut buf) {
+            Ok(read) => read,
+            Err(err) => {
+                return Err(Error::Io(format!(
+                    "Cannot read file {}: {}", path.display(), err
+                )))
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        digest.update(&buf[..read]);
+    }
+    Ok((name, digest.finish()))
 }
 
-fn load_file(path: &Path) -> Result<Vec<u8>, ()> {
+fn load_file(path: &Path) -> Result<Vec<u8>, Error> {
     let mut file = match File::open(path) {
         Ok(file) => file,
         Err(err) => {
-            eprintln!("Failed to open file {}: {}", path.display(), err);
-            return Err(())
+            return Err(Error::Io(format!(
+                "Failed to open file {}: {}", path.display(), err
+            )))
         }
     };
     let mut res = Vec::new();
     if let Err(err) = file.read_to_end(&mut res) {
-        eprintln!(
-            "Failed to read file {}: {}",
-            path.display(), err
-        );
-        return Err(())
+        return Err(Error::Io(format!(
+            "Failed to read file {}: {}", path.display(), err
+        )))
     }
     Ok(res)
 }
 
-fn save_file(path: &Path, content: &[u8]) -> Result<(), ()> {
+fn save_file(path: &Path, content: &[u8]) -> Result<(), Error> {
     let mut file = match File::create(path) {
         Ok(file) => file,
         Err(err) => {
-            eprintln!("Failed to open file {}: {}", path.display(), err);
-            return Err(())
+            return Err(Error::Io(format!(
+                "Failed to open file {}: {}", path.display(), err
+            )))
         }
     };
     if let Err(err) = file.write_all(content) {
-        eprintln!("Failed to write to file {}: {}", path.display(), err);
-        Err(())
+        Err(Error::Io(format!(
+            "Failed to write to file {}: {}", path.display(), err
+        )))
     }
     else {
         Ok(())
     }
 }
 
+/// Writes text to stdout.
+///
+/// If stdout has been closed, for instance because it is piped into a
+/// command that has exited, the text is silently dropped.
+fn write_stdout(text: &str) -> Result<(), Error> {
+    let mut stdout = std::io::stdout().lock();
+    match stdout.write_all(text.as_bytes()).and_then(|_| stdout.flush()) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        Err(err) => {
+            Err(Error::Io(format!("Failed to write to stdout: {}", err)))
+        }
+    }
+}
+
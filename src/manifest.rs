@@ -0,0 +1,88 @@
+//! Building manifests.
+
+use bcder::Mode;
+use bcder::encode::Values;
+use bytes::Bytes;
+use rpki::crypto::{DigestAlgorithm, Signer};
+use rpki::oid;
+use rpki::repository::manifest::{FileAndHash, ManifestContent};
+use rpki::repository::resources::{AsResources, IpResources};
+use rpki::repository::x509::{Serial, Time};
+use crate::error::Error;
+use crate::sigobj::SignedObjectBuilder;
+
+
+//------------ ManifestBuilder -----------------------------------------------
+
+/// A builder for a manifest.
+#[derive(Clone, Debug)]
+pub struct ManifestBuilder {
+    /// The manifest number.
+    manifest_number: Serial,
+
+    /// The time of this update.
+    this_update: Time,
+
+    /// The time of the next update.
+    next_update: Time,
+
+    /// The files listed on the manifest.
+    files: Vec<FileAndHash<Bytes, Bytes>>,
+}
+
+impl ManifestBuilder {
+    pub fn new(
+        manifest_number: Serial, this_update: Time, next_update: Time
+    ) -> Self {
+        ManifestBuilder {
+            manifest_number,
+            this_update,
+            next_update,
+            files: Vec::new(),
+        }
+    }
+
+    /// Returns the digest algorithm used for the file hashes.
+    pub fn digest_algorithm(&self) -> DigestAlgorithm {
+        DigestAlgorithm::default()
+    }
+
+    /// Adds a file with the given name and hash.
+    pub fn push(&mut self, name: Bytes, hash: Bytes) {
+        self.files.push(FileAndHash::new(name, hash))
+    }
+
+    /// Adds a file with the given name and content.
+    pub fn push_content(&mut self, name: Bytes, content: &[u8]) {
+        let hash = self.digest_algorithm().digest(content);
+        self.push(name, Bytes::copy_from_slice(hash.as_ref()))
+    }
+
+    /// Signs the manifest with the issuer’s key and returns its encoding.
+    ///
+    /// Unless they have been set explicitly in `sigobj`, the EE certificate
+    /// inherits all resources.
+    pub fn build<S: Signer>(
+        self,
+        mut sigobj: SignedObjectBuilder,
+        signer: &S,
+        issuer_key: &S::KeyId,
+    ) -> Result<Bytes, Error> {
+        let content = ManifestContent::new(
+            self.manifest_number, self.this_update, self.next_update,
+            DigestAlgorithm::default(), self.files
+        ).encode_ref().to_captured(Mode::Der);
+        if !sigobj.v4_resources().is_present() {
+            sigobj.set_v4_resources(IpResources::inherit());
+        }
+        if !sigobj.v6_resources().is_present() {
+            sigobj.set_v6_resources(IpResources::inherit());
+        }
+        if !sigobj.as_resources().is_present() {
+            sigobj.set_as_resources(AsResources::inherit());
+        }
+        Ok(sigobj.finalize(
+            oid::CT_RPKI_MANIFEST, content.as_slice(), signer, issuer_key
+        )?.into_bytes())
+    }
+}
@@ -0,0 +1,131 @@
+//! PEM encoding of keys, certificates, and CRLs.
+//!
+//! Keys, certificates, and CRLs can be written either as plain DER or as
+//! PEM, i.e., base64 encoded DER wrapped in BEGIN and END lines as
+//! described in RFC 7468. When reading keys, certificates, or CRLs, the
+//! format is detected from the content of the file.
+
+use std::str;
+use std::path::Path;
+use std::str::FromStr;
+use mkrpki::Error;
+use super::{load_file, save_file};
+
+
+//------------ Labels --------------------------------------------------------
+
+/// The label for a certificate.
+pub const CERTIFICATE: &str = "CERTIFICATE";
+
+/// The label for a CRL.
+pub const CRL: &str = "X509 CRL";
+
+/// The label for a public key.
+pub const PUBLIC_KEY: &str = "PUBLIC KEY";
+
+/// The label for an RSA private key in PKCS#1 format.
+pub const RSA_PRIVATE_KEY: &str = "RSA PRIVATE KEY";
+
+/// The label for an EC private key in SEC 1 format.
+pub const EC_PRIVATE_KEY: &str = "EC PRIVATE KEY";
+
+
+//------------ Format --------------------------------------------------------
+
+/// The format of an output file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Der,
+    Pem,
+}
+
+impl Format {
+    /// Encodes DER data in this format.
+    pub fn encode(self, label: &str, der: &[u8]) -> Vec<u8> {
+        match self {
+            Format::Der => der.to_vec(),
+            Format::Pem => encode(label, der),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "der" => Ok(Format::Der),
+            "pem" => Ok(Format::Pem),
+            _ => Err(format!("Unknown format '{}'", s))
+        }
+    }
+}
+
+
+//------------ Functions -----------------------------------------------------
+
+/// Encodes DER data as PEM with the given label.
+pub fn encode(label: &str, der: &[u8]) -> Vec<u8> {
+    let data = base64::encode(der);
+    let mut res = format!("-----BEGIN {}-----\n", label);
+    for line in data.as_bytes().chunks(64) {
+        res.push_str(&String::from_utf8_lossy(line));
+        res.push('\n');
+    }
+    res.push_str(&format!("-----END {}-----\n", label));
+    res.into_bytes()
+}
+
+/// Returns whether the data looks like PEM.
+pub fn is_pem(data: &[u8]) -> bool {
+    let start = data.iter().position(|ch| !ch.is_ascii_whitespace());
+    match start {
+        Some(start) => data[start..].starts_with(b"-----BEGIN "),
+        None => false
+    }
+}
+
+/// Decodes data that is either DER or PEM into DER.
+///
+/// Only the first PEM block is used if there are several.
+pub fn decode(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !is_pem(&data) {
+        return Ok(data)
+    }
+    let text = match str::from_utf8(&data) {
+        Ok(text) => text,
+        Err(_) => return Err("invalid PEM".into())
+    };
+    let mut lines = text.lines().map(str::trim).skip_while(|line| {
+        line.is_empty()
+    });
+    let label = lines.next().and_then(|line| {
+        line.strip_prefix("-----BEGIN ")?.strip_suffix("-----")
+    }).ok_or("invalid PEM begin line")?;
+    let end = format!("-----END {}-----", label);
+    let mut body = String::new();
+    loop {
+        match lines.next() {
+            Some(line) if line == end => break,
+            Some(line) => body.push_str(line),
+            None => return Err("missing PEM end line".into())
+        }
+    }
+    base64::decode(&body).map_err(|_| "invalid PEM encoding".into())
+}
+
+/// Loads a file that is either DER or PEM and returns the DER data.
+pub fn load_der(path: &Path) -> Result<Vec<u8>, Error> {
+    decode(load_file(path)?).map_err(|err| {
+        Error::Encoding(
+            format!("Failed to load file {}: {}", path.display(), err)
+        )
+    })
+}
+
+/// Saves DER data to a file in the given format.
+pub fn save(
+    path: &Path, format: Format, label: &str, der: &[u8]
+) -> Result<(), Error> {
+    save_file(path, &format.encode(label, der))
+}
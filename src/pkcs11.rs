@@ -0,0 +1,656 @@
+//! Signing with keys kept in a PKCS#11 token.
+//!
+//! Wherever a private key file is expected, a PKCS#11 URI as defined in
+//! RFC 7512 can be given instead. It identifies the module to load, the
+//! token, and the private key to use. For instance:
+//!
+//! ```text
+//! pkcs11:token=ca;object=ta?module-path=/usr/lib/softhsm/libsofthsm2.so
+//!     &pin-value=1234
+//! ```
+//!
+//! The module is accessed directly through the C API, so only the handful
+//! of functions necessary for finding a key and signing with it are
+//! declared here. Only RSA keys are supported since these are the only keys
+//! a CA can use for signing.
+
+use std::{io, ptr};
+use std::ffi::c_void;
+use std::os::raw::c_ulong;
+use std::path::PathBuf;
+use std::str::FromStr;
+use bytes::Bytes;
+use libloading::Library;
+use rpki::crypto::{
+    PublicKey, PublicKeyFormat, Signature, SignatureAlgorithm, Signer,
+    SigningError
+};
+use rpki::crypto::signer::{KeyError, SigningAlgorithm};
+use rpki::crypto::softsigner::OpenSslSigner;
+use super::load_file;
+
+
+//------------ Pkcs11Uri -----------------------------------------------------
+
+/// A PKCS#11 URI identifying a private key.
+#[derive(Clone, Debug, Default)]
+pub struct Pkcs11Uri {
+    /// The path of the PKCS#11 module to load.
+    module_path: PathBuf,
+
+    /// The ID of the slot containing the token.
+    slot_id: Option<c_ulong>,
+
+    /// The label of the token.
+    token: Option<String>,
+
+    /// The label of the key.
+    object: Option<String>,
+
+    /// The ID of the key.
+    id: Option<Vec<u8>>,
+
+    /// The user PIN for the token.
+    pin: Option<String>,
+}
+
+impl Pkcs11Uri {
+    /// Returns whether a key reference is a PKCS#11 URI.
+    pub fn is_pkcs11(s: &str) -> bool {
+        s.starts_with("pkcs11:")
+    }
+}
+
+impl FromStr for Pkcs11Uri {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s.strip_prefix("pkcs11:") {
+            Some(s) => s,
+            None => return Err("missing 'pkcs11:' scheme".into())
+        };
+        let (path, query) = match s.find('?') {
+            Some(idx) => (&s[..idx], &s[idx + 1..]),
+            None => (s, "")
+        };
+        let mut res = Pkcs11Uri::default();
+        let mut module_path = None;
+        for attr in path.split(';').filter(|attr| !attr.is_empty()) {
+            let (name, value) = split_attr(attr)?;
+            match name {
+                "slot-id" => {
+                    res.slot_id = Some(
+                        c_ulong::from_str(&decode_str(value)?).map_err(|_| {
+                            format!("invalid slot-id '{}'", value)
+                        })?
+                    )
+                }
+                "token" => res.token = Some(decode_str(value)?),
+                "object" => res.object = Some(decode_str(value)?),
+                "id" => res.id = Some(pct_decode(value)?),
+                "type" => {
+                    if value != "private" {
+                        return Err(format!(
+                            "unsupported object type '{}'", value
+                        ))
+                    }
+                }
+                _ => {
+                    return Err(format!("unsupported attribute '{}'", name))
+                }
+            }
+        }
+        for attr in query.split('&').filter(|attr| !attr.is_empty()) {
+            let (name, value) = split_attr(attr)?;
+            match name {
+                "module-path" => module_path = Some(decode_str(value)?),
+                "pin-value" => res.pin = Some(decode_str(value)?),
+                "pin-source" => {
+                    let path = decode_str(value)?;
+                    let path = path.strip_prefix("file:").unwrap_or(&path);
+                    let pin = load_file(path.as_ref()).map_err(|_| {
+                        format!("cannot read PIN from '{}'", path)
+                    })?;
+                    let pin = String::from_utf8(pin).map_err(|_| {
+                        format!("invalid PIN in '{}'", path)
+                    })?;
+                    res.pin = Some(pin.trim_end().into());
+                }
+                _ => {
+                    return Err(format!("unsupported attribute '{}'", name))
+                }
+            }
+        }
+        res.module_path = match module_path {
+            Some(path) => path.into(),
+            None => return Err("missing module-path".into())
+        };
+        if res.object.is_none() && res.id.is_none() {
+            return Err("either object or id must be given".into())
+        }
+        Ok(res)
+    }
+}
+
+
+//------------ Pkcs11Signer --------------------------------------------------
+
+/// A signer using the keys of a PKCS#11 token.
+///
+/// Keys are identified by their object handle. Creating and destroying
+/// keys is not supported. One-off keys for signed objects are created in
+/// software.
+pub struct Pkcs11Signer {
+    /// The function list of the module.
+    functions: *const FunctionList,
+
+    /// The session with the token.
+    session: c_ulong,
+
+    /// The signer for one-off keys.
+    one_off: OpenSslSigner,
+
+    /// The loaded module.
+    ///
+    /// This needs to be kept around for the function list to stay valid.
+    _library: Library,
+}
+
+impl Pkcs11Signer {
+    /// Opens a session with the token identified by the URI.
+    ///
+    /// Returns the signer and the handle of the private key.
+    pub fn open(uri: &Pkcs11Uri) -> Result<(Self, c_ulong), io::Error> {
+        // Safety: Loading a library runs its initialization code. We have
+        // to trust the module given by the user.
+        let library = unsafe { Library::new(&uri.module_path) }.map_err(|err| {
+            other(format!(
+                "cannot load module {}: {}", uri.module_path.display(), err
+            ))
+        })?;
+        let functions = unsafe {
+            let get_function_list = library.get::<
+                unsafe extern "C" fn(*mut *const FunctionList) -> c_ulong
+            >(b"C_GetFunctionList").map_err(|err| {
+                other(format!("not a PKCS#11 module: {}", err))
+            })?;
+            let mut functions = ptr::null();
+            check(get_function_list(&mut functions), "C_GetFunctionList")?;
+            if functions.is_null() {
+                return Err(other("empty PKCS#11 function list"))
+            }
+            functions
+        };
+        let rv = unsafe { ((*functions).initialize)(ptr::null_mut()) };
+        if rv != CKR_CRYPTOKI_ALREADY_INITIALIZED {
+            check(rv, "C_Initialize")?;
+        }
+        let mut res = Pkcs11Signer {
+            functions,
+            session: 0,
+            one_off: OpenSslSigner::new(),
+            _library: library,
+        };
+        let slot = res.find_slot(uri)?;
+        unsafe {
+            check(
+                (res.f().open_session)(
+                    slot, CKF_SERIAL_SESSION, ptr::null_mut(), None,
+                    &mut res.session
+                ),
+                "C_OpenSession"
+            )?;
+        }
+        if let Some(ref pin) = uri.pin {
+            let rv = unsafe {
+                (res.f().login)(
+                    res.session, CKU_USER, pin.as_ptr(), pin.len() as c_ulong
+                )
+            };
+            if rv != CKR_USER_ALREADY_LOGGED_IN {
+                check(rv, "C_Login")?;
+            }
+        }
+        let key = res.find_key(
+            CKO_PRIVATE_KEY, uri.object.as_deref(), uri.id.as_deref()
+        )?.ok_or_else(|| other("private key not found"))?;
+        Ok((res, key))
+    }
+
+    /// Returns the function list.
+    fn f(&self) -> &FunctionList {
+        // Safety: The pointer was checked to be non-null and the library
+        // is kept alive as long as we are.
+        unsafe { &*self.functions }
+    }
+
+    /// Finds the slot for the token given in the URI.
+    fn find_slot(&self, uri: &Pkcs11Uri) -> Result<c_ulong, io::Error> {
+        if let Some(slot) = uri.slot_id {
+            return Ok(slot)
+        }
+        let mut count = 0;
+        unsafe {
+            check(
+                (self.f().get_slot_list)(1, ptr::null_mut(), &mut count),
+                "C_GetSlotList"
+            )?;
+        }
+        let mut slots = vec![0; count as usize];
+        unsafe {
+            check(
+                (self.f().get_slot_list)(1, slots.as_mut_ptr(), &mut count),
+                "C_GetSlotList"
+            )?;
+        }
+        slots.truncate(count as usize);
+        let token = match uri.token {
+            Some(ref token) => token,
+            None => {
+                return slots.first().copied().ok_or_else(|| {
+                    other("no token present")
+                })
+            }
+        };
+        for slot in slots {
+            let mut info = TokenInfo::default();
+            unsafe {
+                check(
+                    (self.f().get_token_info)(slot, &mut info),
+                    "C_GetTokenInfo"
+                )?;
+            }
+            if info.label() == token.as_bytes() {
+                return Ok(slot)
+            }
+        }
+        Err(other(format!("token '{}' not found", token)))
+    }
+
+    /// Finds a key object by its class, label, and ID.
+    fn find_key(
+        &self, class: c_ulong, label: Option<&str>, id: Option<&[u8]>
+    ) -> Result<Option<c_ulong>, io::Error> {
+        let mut template = vec![Attribute::new(CKA_CLASS, &class)];
+        if let Some(label) = label {
+            template.push(Attribute::from_slice(CKA_LABEL, label.as_bytes()));
+        }
+        if let Some(id) = id {
+            template.push(Attribute::from_slice(CKA_ID, id));
+        }
+        let mut handle = 0;
+        let mut count = 0;
+        unsafe {
+            check(
+                (self.f().find_objects_init)(
+                    self.session, template.as_mut_ptr(),
+                    template.len() as c_ulong
+                ),
+                "C_FindObjectsInit"
+            )?;
+            let rv = (self.f().find_objects)(
+                self.session, &mut handle, 1, &mut count
+            );
+            check((self.f().find_objects_final)(self.session),
+                "C_FindObjectsFinal"
+            )?;
+            check(rv, "C_FindObjects")?;
+        }
+        Ok(if count == 0 { None } else { Some(handle) })
+    }
+
+    /// Returns the value of an attribute of an object.
+    fn get_attribute(
+        &self, object: c_ulong, attr_type: c_ulong
+    ) -> Result<Vec<u8>, io::Error> {
+        let mut attr = Attribute {
+            attr_type, value: ptr::null_mut(), value_len: 0
+        };
+        unsafe {
+            check(
+                (self.f().get_attribute_value)(
+                    self.session, object, &mut attr, 1
+                ),
+                "C_GetAttributeValue"
+            )?;
+        }
+        if attr.value_len == CK_UNAVAILABLE_INFORMATION {
+            return Err(other("attribute not available"))
+        }
+        let mut res = vec![0u8; attr.value_len as usize];
+        attr.value = res.as_mut_ptr() as *mut c_void;
+        unsafe {
+            check(
+                (self.f().get_attribute_value)(
+                    self.session, object, &mut attr, 1
+                ),
+                "C_GetAttributeValue"
+            )?;
+        }
+        res.truncate(attr.value_len as usize);
+        Ok(res)
+    }
+
+    /// Returns the public key for a private key.
+    ///
+    /// The modulus and public exponent are taken from the private key
+    /// object if available or from the public key object with the same ID
+    /// otherwise.
+    fn public_key(&self, key: c_ulong) -> Result<PublicKey, io::Error> {
+        let key_type = self.get_attribute(key, CKA_KEY_TYPE)?;
+        if key_type.iter().any(|&octet| octet != 0) {
+            return Err(other("only RSA keys are supported"))
+        }
+        let key = match self.get_attribute(key, CKA_PUBLIC_EXPONENT) {
+            Ok(_) => key,
+            Err(_) => {
+                let id = self.get_attribute(key, CKA_ID)?;
+                self.find_key(CKO_PUBLIC_KEY, None, Some(&id))?.ok_or_else(
+                    || other("public key not found")
+                )?
+            }
+        };
+        let modulus = self.get_attribute(key, CKA_MODULUS)?;
+        let exponent = self.get_attribute(key, CKA_PUBLIC_EXPONENT)?;
+        PublicKey::rsa_from_components(&modulus, &exponent).map_err(|_| {
+            other("invalid RSA public key")
+        })
+    }
+}
+
+impl Signer for Pkcs11Signer {
+    type KeyId = c_ulong;
+    type Error = io::Error;
+
+    fn create_key(
+        &self, _algorithm: PublicKeyFormat
+    ) -> Result<Self::KeyId, Self::Error> {
+        Err(other("creating keys is not supported"))
+    }
+
+    fn get_key_info(
+        &self, key: &Self::KeyId
+    ) -> Result<PublicKey, KeyError<Self::Error>> {
+        self.public_key(*key).map_err(KeyError::Signer)
+    }
+
+    fn destroy_key(
+        &self, _key: &Self::KeyId
+    ) -> Result<(), KeyError<Self::Error>> {
+        Err(KeyError::Signer(other("destroying keys is not supported")))
+    }
+
+    fn sign<Alg: SignatureAlgorithm, D: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Self::KeyId,
+        algorithm: Alg,
+        data: &D
+    ) -> Result<Signature<Alg>, SigningError<Self::Error>> {
+        match algorithm.signing_algorithm() {
+            SigningAlgorithm::RsaSha256 => { }
+            _ => return Err(SigningError::IncompatibleKey)
+        }
+        let data = data.as_ref();
+        let mut mechanism = Mechanism {
+            mechanism: CKM_SHA256_RSA_PKCS,
+            parameter: ptr::null_mut(),
+            parameter_len: 0,
+        };
+        let mut len = 0;
+        unsafe {
+            check(
+                (self.f().sign_init)(self.session, &mut mechanism, *key),
+                "C_SignInit"
+            )?;
+            check(
+                (self.f().sign)(
+                    self.session, data.as_ptr(), data.len() as c_ulong,
+                    ptr::null_mut(), &mut len
+                ),
+                "C_Sign"
+            )?;
+        }
+        let mut res = vec![0u8; len as usize];
+        unsafe {
+            check(
+                (self.f().sign)(
+                    self.session, data.as_ptr(), data.len() as c_ulong,
+                    res.as_mut_ptr(), &mut len
+                ),
+                "C_Sign"
+            )?;
+        }
+        res.truncate(len as usize);
+        Ok(Signature::new(algorithm, Bytes::from(res)))
+    }
+
+    fn sign_one_off<Alg: SignatureAlgorithm, D: AsRef<[u8]> + ?Sized>(
+        &self,
+        algorithm: Alg,
+        data: &D
+    ) -> Result<(Signature<Alg>, PublicKey), Self::Error> {
+        self.one_off.sign_one_off(algorithm, data)
+    }
+
+    fn rand(&self, target: &mut [u8]) -> Result<(), Self::Error> {
+        self.one_off.rand(target)
+    }
+}
+
+impl Drop for Pkcs11Signer {
+    fn drop(&mut self) {
+        unsafe {
+            (self.f().logout)(self.session);
+            (self.f().close_session)(self.session);
+            (self.f().finalize)(ptr::null_mut());
+        }
+    }
+}
+
+
+//------------ Cryptoki Types ------------------------------------------------
+
+const CKR_USER_ALREADY_LOGGED_IN: c_ulong = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: c_ulong = 0x191;
+const CKF_SERIAL_SESSION: c_ulong = 0x04;
+const CKU_USER: c_ulong = 1;
+const CKO_PUBLIC_KEY: c_ulong = 2;
+const CKO_PRIVATE_KEY: c_ulong = 3;
+const CKA_CLASS: c_ulong = 0x000;
+const CKA_LABEL: c_ulong = 0x003;
+const CKA_KEY_TYPE: c_ulong = 0x100;
+const CKA_ID: c_ulong = 0x102;
+const CKA_MODULUS: c_ulong = 0x120;
+const CKA_PUBLIC_EXPONENT: c_ulong = 0x122;
+const CKM_SHA256_RSA_PKCS: c_ulong = 0x040;
+const CK_UNAVAILABLE_INFORMATION: c_ulong = !0;
+
+/// A function pointer we don’t use.
+type Unused = Option<unsafe extern "C" fn()>;
+
+/// The `CK_FUNCTION_LIST` up to and including `C_Sign`.
+///
+/// We only ever access the list through a pointer handed to us by the
+/// module, so leaving out the remaining functions is fine.
+#[repr(C)]
+struct FunctionList {
+    version: [u8; 2],
+    initialize: unsafe extern "C" fn(*mut c_void) -> c_ulong,
+    finalize: unsafe extern "C" fn(*mut c_void) -> c_ulong,
+    get_info: Unused,
+    get_function_list: Unused,
+    get_slot_list: unsafe extern "C" fn(
+        u8, *mut c_ulong, *mut c_ulong
+    ) -> c_ulong,
+    get_slot_info: Unused,
+    get_token_info: unsafe extern "C" fn(c_ulong, *mut TokenInfo) -> c_ulong,
+    get_mechanism_list: Unused,
+    get_mechanism_info: Unused,
+    init_token: Unused,
+    init_pin: Unused,
+    set_pin: Unused,
+    open_session: unsafe extern "C" fn(
+        c_ulong, c_ulong, *mut c_void, Option<unsafe extern "C" fn()>,
+        *mut c_ulong
+    ) -> c_ulong,
+    close_session: unsafe extern "C" fn(c_ulong) -> c_ulong,
+    close_all_sessions: Unused,
+    get_session_info: Unused,
+    get_operation_state: Unused,
+    set_operation_state: Unused,
+    login: unsafe extern "C" fn(
+        c_ulong, c_ulong, *const u8, c_ulong
+    ) -> c_ulong,
+    logout: unsafe extern "C" fn(c_ulong) -> c_ulong,
+    create_object: Unused,
+    copy_object: Unused,
+    destroy_object: Unused,
+    get_object_size: Unused,
+    get_attribute_value: unsafe extern "C" fn(
+        c_ulong, c_ulong, *mut Attribute, c_ulong
+    ) -> c_ulong,
+    set_attribute_value: Unused,
+    find_objects_init: unsafe extern "C" fn(
+        c_ulong, *mut Attribute, c_ulong
+    ) -> c_ulong,
+    find_objects: unsafe extern "C" fn(
+        c_ulong, *mut c_ulong, c_ulong, *mut c_ulong
+    ) -> c_ulong,
+    find_objects_final: unsafe extern "C" fn(c_ulong) -> c_ulong,
+    encrypt_init: Unused,
+    encrypt: Unused,
+    encrypt_update: Unused,
+    encrypt_final: Unused,
+    decrypt_init: Unused,
+    decrypt: Unused,
+    decrypt_update: Unused,
+    decrypt_final: Unused,
+    digest_init: Unused,
+    digest: Unused,
+    digest_update: Unused,
+    digest_key: Unused,
+    digest_final: Unused,
+    sign_init: unsafe extern "C" fn(
+        c_ulong, *mut Mechanism, c_ulong
+    ) -> c_ulong,
+    sign: unsafe extern "C" fn(
+        c_ulong, *const u8, c_ulong, *mut u8, *mut c_ulong
+    ) -> c_ulong,
+}
+
+/// A `CK_ATTRIBUTE`.
+#[repr(C)]
+struct Attribute {
+    attr_type: c_ulong,
+    value: *mut c_void,
+    value_len: c_ulong,
+}
+
+impl Attribute {
+    /// Creates an attribute referencing a value.
+    fn new<T>(attr_type: c_ulong, value: &T) -> Self {
+        Attribute {
+            attr_type,
+            value: value as *const T as *mut c_void,
+            value_len: std::mem::size_of::<T>() as c_ulong,
+        }
+    }
+
+    /// Creates an attribute referencing a byte slice.
+    fn from_slice(attr_type: c_ulong, value: &[u8]) -> Self {
+        Attribute {
+            attr_type,
+            value: value.as_ptr() as *mut c_void,
+            value_len: value.len() as c_ulong,
+        }
+    }
+}
+
+/// A `CK_MECHANISM`.
+#[repr(C)]
+struct Mechanism {
+    mechanism: c_ulong,
+    parameter: *mut c_void,
+    parameter_len: c_ulong,
+}
+
+/// A `CK_TOKEN_INFO`.
+///
+/// We only need the label, the rest is generously sized padding.
+#[repr(C)]
+struct TokenInfo {
+    label: [u8; 32],
+    rest: [c_ulong; 32],
+}
+
+impl TokenInfo {
+    /// Returns the label with the padding removed.
+    fn label(&self) -> &[u8] {
+        let len = self.label.iter().rposition(|&ch| ch != b' ').map(|pos| {
+            pos + 1
+        }).unwrap_or(0);
+        &self.label[..len]
+    }
+}
+
+impl Default for TokenInfo {
+    fn default() -> Self {
+        TokenInfo { label: [b' '; 32], rest: [0; 32] }
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Converts a return value into a result.
+fn check(rv: c_ulong, function: &str) -> Result<(), io::Error> {
+    if rv == 0 {
+        Ok(())
+    }
+    else {
+        Err(other(format!("{} failed with error 0x{:x}", function, rv)))
+    }
+}
+
+/// Creates an error with the given message.
+fn other(msg: impl Into<String>) -> io::Error {
+    io::Error::other(msg.into())
+}
+
+/// Splits a URI attribute into name and value.
+fn split_attr(attr: &str) -> Result<(&str, &str), String> {
+    match attr.find('=') {
+        Some(idx) => Ok((&attr[..idx], &attr[idx + 1..])),
+        None => Err(format!("invalid attribute '{}'", attr))
+    }
+}
+
+/// Decodes a percent-encoded string.
+fn decode_str(value: &str) -> Result<String, String> {
+    String::from_utf8(pct_decode(value)?).map_err(|_| {
+        format!("invalid value '{}'", value)
+    })
+}
+
+/// Decodes percent-encoded octets.
+fn pct_decode(value: &str) -> Result<Vec<u8>, String> {
+    let mut res = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(ch) = bytes.next() {
+        if ch != b'%' {
+            res.push(ch);
+            continue
+        }
+        let hex = [
+            bytes.next().unwrap_or(b' '), bytes.next().unwrap_or(b' ')
+        ];
+        let hex = std::str::from_utf8(&hex).ok().and_then(|hex| {
+            u8::from_str_radix(hex, 16).ok()
+        });
+        match hex {
+            Some(octet) => res.push(octet),
+            None => return Err(format!("invalid value '{}'", value))
+        }
+    }
+    Ok(res)
+}
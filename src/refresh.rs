@@ -0,0 +1,476 @@
+//! Refreshing an existing repository.
+//!
+//! All CRLs and manifests found in a repository are re-issued with new
+//! update times and the next CRL or manifest number. Optionally, CA
+//! certificates and signed objects are re-issued with a fresh validity,
+//! too. Everything else about the objects stays the same.
+//!
+//! The objects are signed with the keys found in the key directories,
+//! matched to the objects via their authority key identifiers. Re-issued
+//! certificates get a new serial number following the largest serial
+//! number already used by the issuer. The replaced certificates are added
+//! to the issuer’s CRL.
+
+use std::fs;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use bytes::Bytes;
+use mkrpki::{CrlBuilder, Error, ManifestBuilder, TaBuilder};
+use mkrpki::sigobj::SignedObjectBuilder;
+use rpki::crypto::{KeyIdentifier, Signer};
+use rpki::repository::cert::Cert;
+use rpki::repository::crl::{Crl, CrlEntry};
+use rpki::repository::manifest::Manifest;
+use rpki::repository::sigobj::SignedObject;
+use rpki::repository::x509::{Serial, Time, Validity};
+use rpki::uri;
+use structopt::StructOpt;
+use crate::clock;
+use crate::clock::Period;
+use crate::reissue::{
+    decode_cert, decode_object, reissue_ca_cert, resign_object
+};
+use crate::signer::{KeyRef, KeySigner};
+use super::{create_signer, digest_file, load_file, save_file};
+
+
+//------------ Refresh -------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Refresh {
+    /// Path to the directory with the repository.
+    #[structopt(long="repository")]
+    repository: PathBuf,
+
+    /// Path to a directory with private keys of the CAs.
+    #[structopt(long="keys")]
+    keys: Vec<PathBuf>,
+
+    /// Time until the next update of CRLs and manifests, in days if just a
+    /// number.
+    #[structopt(long="next-days", default_value="30")]
+    next_days: Period,
+
+    /// Also re-issue all CA certificates.
+    #[structopt(long="certs")]
+    certs: bool,
+
+    /// Also re-issue all signed objects other than manifests.
+    #[structopt(long="objects")]
+    objects: bool,
+
+    /// Duration of validity of re-issued certificates and objects, in days
+    /// if just a number.
+    #[structopt(long="days", default_value="30")]
+    valid_days: Period,
+}
+
+impl Refresh {
+    pub fn run(self) -> Result<(), Error> {
+        if self.keys.is_empty() {
+            return Err(Error::Usage(
+                "At least one --keys directory must be given.".into()
+            ))
+        }
+        let now = clock::now();
+        let mut refresher = Refresher {
+            keys: HashMap::new(),
+            serials: HashMap::new(),
+            replaced: HashMap::new(),
+            now,
+            validity: Validity::new(
+                now, now + self.valid_days.duration()
+            ),
+            next_update: now + self.next_days.duration(),
+        };
+        for dir in &self.keys {
+            refresher.load_keys(dir)?;
+        }
+
+        // Decode everything first to learn the serial numbers in use.
+        let mut files = Vec::new();
+        collect_files(&self.repository, &mut files)?;
+        let mut certs = Vec::new();
+        let mut objects = Vec::new();
+        let mut crls = Vec::new();
+        let mut manifests = Vec::new();
+        for path in files {
+            let ext = path.extension().and_then(OsStr::to_str);
+            let ext = ext.unwrap_or("").to_string();
+            match ext.as_str() {
+                "cer" => {
+                    let cert = decode_cert(&path, load(&path)?)?;
+                    refresher.see(issuer_of(&cert), cert.serial_number());
+                    certs.push((path, cert));
+                }
+                "asa" | "gbr" | "roa" | "sig" | "tak" => {
+                    let object = decode_object(&path, load(&path)?)?;
+                    let ee = object.cert();
+                    refresher.see(issuer_of(ee), ee.serial_number());
+                    objects.push((path, object));
+                }
+                "crl" => {
+                    let crl = Crl::decode(load(&path)?).map_err(|err| {
+                        Error::Encoding(format!(
+                            "Failed to decode CRL {}: {}",
+                            path.display(), err
+                        ))
+                    })?;
+                    crls.push((path, crl));
+                }
+                "mft" => {
+                    let mft = Manifest::decode(
+                        load(&path)?, false
+                    ).map_err(|err| {
+                        Error::Encoding(format!(
+                            "Failed to decode manifest {}: {}",
+                            path.display(), err
+                        ))
+                    })?;
+                    let ee = mft.cert();
+                    refresher.see(issuer_of(ee), ee.serial_number());
+                    manifests.push((path, mft));
+                }
+                _ => { }
+            }
+        }
+
+        if self.certs {
+            for (path, cert) in &certs {
+                refresher.refresh_cert(path, cert)?;
+            }
+        }
+        if self.objects {
+            for (path, object) in &objects {
+                refresher.refresh_object(path, object)?;
+            }
+        }
+        for (_, mft) in &manifests {
+            let ee = mft.cert();
+            refresher.replace(
+                issuer_of(ee), ee.serial_number(), ee.validity().not_after()
+            );
+        }
+        for (path, crl) in &crls {
+            refresher.refresh_crl(path, crl)?;
+        }
+        for (path, mft) in &manifests {
+            refresher.refresh_manifest(path, mft)?;
+        }
+        Ok(())
+    }
+}
+
+
+//------------ Refresher -----------------------------------------------------
+
+/// The state kept while refreshing a repository.
+struct Refresher {
+    /// The signers for all known keys by their key identifier.
+    keys: HashMap<KeyIdentifier, (KeySigner, KeyRef)>,
+
+    /// The largest serial number used by each issuer.
+    serials: HashMap<KeyIdentifier, Serial>,
+
+    /// The certificates replaced by each issuer.
+    replaced: HashMap<KeyIdentifier, Vec<CrlEntry>>,
+
+    /// The time of refreshing.
+    now: Time,
+
+    /// The validity of re-issued certificates and objects.
+    validity: Validity,
+
+    /// The next update time of CRLs and manifests.
+    next_update: Time,
+}
+
+impl Refresher {
+    /// Loads all private keys in a directory.
+    ///
+    /// These are all the files with the extension `.key`.
+    fn load_keys(&mut self, dir: &Path) -> Result<(), Error> {
+        let entries = fs::read_dir(dir).map_err(|err| {
+            Error::Io(format!(
+                "Failed to read directory {}: {}", dir.display(), err
+            ))
+        })?;
+        for entry in entries {
+            let path = entry.map_err(|err| {
+                Error::Io(format!(
+                    "Failed to read directory {}: {}", dir.display(), err
+                ))
+            })?.path();
+            if path.extension().and_then(OsStr::to_str) != Some("key") {
+                continue
+            }
+            let (signer, key) = create_signer(&path)?;
+            let key_id = signer.get_key_info(&key)?.key_identifier();
+            self.keys.insert(key_id, (signer, key));
+        }
+        Ok(())
+    }
+
+    /// Returns the signer and key for an issuer.
+    fn key(
+        &self, issuer: KeyIdentifier, path: &Path
+    ) -> Result<(&KeySigner, &KeyRef), Error> {
+        match self.keys.get(&issuer) {
+            Some((signer, key)) => Ok((signer, key)),
+            None => {
+                Err(Error::Key(format!(
+                    "No key for issuer {} of {}.", issuer, path.display()
+                )))
+            }
+        }
+    }
+
+    /// Records a serial number used by an issuer.
+    fn see(&mut self, issuer: KeyIdentifier, serial: Serial) {
+        let last = self.serials.entry(issuer).or_default();
+        if serial > *last {
+            *last = serial
+        }
+    }
+
+    /// Returns a new serial number for an issuer.
+    ///
+    /// If the issuer uses sequential serial numbers, the number following
+    /// the largest one in use is returned. Otherwise, the serial numbers
+    /// are assumed to be random and a new random serial number is created.
+    fn next_serial(
+        &mut self, issuer: KeyIdentifier, path: &Path
+    ) -> Result<Serial, Error> {
+        let last = self.serials.get(&issuer).copied().unwrap_or_default();
+        let res = match u64::from_str(&last.to_string()) {
+            Ok(last) => Serial::from(last.saturating_add(1)),
+            Err(_) => {
+                let (signer, _) = self.key(issuer, path)?;
+                Serial::random(signer).map_err(|err| {
+                    Error::Key(format!(
+                        "Failed to create serial number: {}", err
+                    ))
+                })?
+            }
+        };
+        self.see(issuer, res);
+        Ok(res)
+    }
+
+    /// Records a certificate replaced by a re-issued one.
+    ///
+    /// Unless the certificate has expired anyway, it will be added to the
+    /// issuer’s CRL.
+    fn replace(
+        &mut self, issuer: KeyIdentifier, serial: Serial, not_after: Time
+    ) {
+        if not_after > self.now {
+            self.replaced.entry(issuer).or_default().push(
+                CrlEntry::new(serial, self.now)
+            )
+        }
+    }
+
+    /// Re-issues a CA certificate with a fresh validity.
+    fn refresh_cert(&mut self, path: &Path, cert: &Cert) -> Result<(), Error> {
+        if !cert.is_ca() {
+            eprintln!(
+                "Warning: skipping {}: not a CA certificate.", path.display()
+            );
+            return Ok(())
+        }
+        let issuer = issuer_of(cert);
+        let serial = self.next_serial(issuer, path)?;
+        let (signer, key) = self.key(issuer, path)?;
+        let data = if cert.is_self_signed() {
+            let mut ta = TaBuilder::new(
+                serial, self.validity,
+                required(cert.ca_repository(), "caRepository", path)?,
+                required(cert.rpki_manifest(), "rpkiManifest", path)?,
+            );
+            ta.set_rpki_notify(cert.rpki_notify().cloned());
+            ta.set_v4_resources(cert.v4_resources().clone());
+            ta.set_v6_resources(cert.v6_resources().clone());
+            ta.set_as_resources(cert.as_resources().clone());
+            ta.build(signer, key)?
+        }
+        else {
+            let data = reissue_ca_cert(
+                path, cert, serial, self.validity,
+                required(cert.crl_uri(), "CRL distribution point", path)?,
+                required(cert.ca_issuer(), "CA issuer", path)?,
+                signer, key
+            )?;
+            self.replace(
+                issuer, cert.serial_number(), cert.validity().not_after()
+            );
+            data
+        };
+        save_file(path, &data)?;
+        eprintln!("Refreshed: {}", path.display());
+        Ok(())
+    }
+
+    /// Re-signs a signed object with a fresh validity.
+    fn refresh_object(
+        &mut self, path: &Path, object: &SignedObject
+    ) -> Result<(), Error> {
+        let ee = object.cert();
+        let issuer = issuer_of(ee);
+        let serial = self.next_serial(issuer, path)?;
+        let (signer, key) = self.key(issuer, path)?;
+        let sigobj = SignedObjectBuilder::new(
+            serial, self.validity,
+            required(ee.crl_uri(), "CRL distribution point", path)?,
+            required(ee.ca_issuer(), "CA issuer", path)?,
+            ee.signed_object().cloned()
+        );
+        let data = resign_object(object, sigobj, signer, key)?;
+        self.replace(issuer, ee.serial_number(), ee.validity().not_after());
+        save_file(path, &data)?;
+        eprintln!("Refreshed: {}", path.display());
+        Ok(())
+    }
+
+    /// Re-issues a CRL with the next CRL number.
+    ///
+    /// All certificates replaced by the issuer are added to the CRL.
+    fn refresh_crl(&self, path: &Path, crl: &Crl) -> Result<(), Error> {
+        let issuer = *crl.authority_key_identifier();
+        let (signer, key) = self.key(issuer, path)?;
+        let mut res = CrlBuilder::new(
+            self.now, self.next_update, increment(crl.crl_number(), path)?
+        );
+        res.extend(crl.revoked_certs().iter());
+        if let Some(replaced) = self.replaced.get(&issuer) {
+            res.extend(replaced.iter().copied());
+        }
+        save_file(path, &res.build(signer, key)?)?;
+        eprintln!("Refreshed: {}", path.display());
+        Ok(())
+    }
+
+    /// Re-issues a manifest with the next manifest number.
+    ///
+    /// The files listed on the manifest stay the same but their hashes are
+    /// calculated anew from the files next to the manifest. If a file is
+    /// missing, its old hash is kept.
+    fn refresh_manifest(
+        &mut self, path: &Path, mft: &Manifest
+    ) -> Result<(), Error> {
+        let ee = mft.cert();
+        let issuer = issuer_of(ee);
+        let serial = self.next_serial(issuer, path)?;
+        let content = mft.content();
+        let mut res = ManifestBuilder::new(
+            increment(content.manifest_number(), path)?,
+            self.now, self.next_update
+        );
+        let alg = res.digest_algorithm();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for item in content.iter() {
+            let file = match std::str::from_utf8(item.file()) {
+                Ok(file) => dir.join(file),
+                Err(_) => {
+                    return Err(Error::Encoding(format!(
+                        "Invalid file name on manifest {}.", path.display()
+                    )))
+                }
+            };
+            if file.exists() {
+                let (_, digest) = digest_file(alg, &file)?;
+                res.push(
+                    item.file().clone(),
+                    Bytes::copy_from_slice(digest.as_ref())
+                );
+            }
+            else {
+                eprintln!(
+                    "Warning: {} is missing. Keeping its hash.",
+                    file.display()
+                );
+                res.push(item.file().clone(), item.hash().clone());
+            }
+        }
+        let sigobj = SignedObjectBuilder::new(
+            serial, Validity::new(self.now, self.next_update),
+            required(ee.crl_uri(), "CRL distribution point", path)?,
+            required(ee.ca_issuer(), "CA issuer", path)?,
+            ee.signed_object().cloned()
+        );
+        let (signer, key) = self.key(issuer, path)?;
+        save_file(path, &res.build(sigobj, signer, key)?)?;
+        eprintln!("Refreshed: {}", path.display());
+        Ok(())
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Collects the paths of all files below a directory.
+fn collect_files(dir: &Path, res: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let entries = fs::read_dir(dir).map_err(|err| {
+        Error::Io(format!(
+            "Failed to read directory {}: {}", dir.display(), err
+        ))
+    })?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        paths.push(entry.map_err(|err| {
+            Error::Io(format!(
+                "Failed to read directory {}: {}", dir.display(), err
+            ))
+        })?.path());
+    }
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            collect_files(&path, res)?;
+        }
+        else {
+            res.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Loads a file.
+fn load(path: &Path) -> Result<Bytes, Error> {
+    load_file(path).map(Bytes::from)
+}
+
+/// Returns the key identifier of the issuer of a certificate.
+///
+/// Self-signed certificates may lack the authority key identifier, so the
+/// subject key identifier is used for them.
+fn issuer_of(cert: &Cert) -> KeyIdentifier {
+    cert.authority_key_identifier().unwrap_or_else(|| {
+        cert.subject_key_identifier()
+    })
+}
+
+/// Returns a URI the certificate must have.
+fn required(
+    uri: Option<&uri::Rsync>, what: &str, path: &Path
+) -> Result<uri::Rsync, Error> {
+    uri.cloned().ok_or_else(|| {
+        Error::Encoding(format!(
+            "Certificate in {} has no {} URI.", path.display(), what
+        ))
+    })
+}
+
+/// Returns the CRL or manifest number following the given one.
+fn increment(number: Serial, path: &Path) -> Result<Serial, Error> {
+    match u128::from_str(&number.to_string()) {
+        Ok(number) if number < u128::MAX => Ok((number + 1).into()),
+        _ => {
+            Err(Error::Encoding(format!(
+                "Cannot increase number {} of {}.", number, path.display()
+            )))
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Re-issuing existing objects.
+//!
+//! Both the `rollover` and the `refresh` commands create new versions of
+//! certificates and signed objects already published. The new version
+//! keeps the content and resources of the existing object and only changes
+//! what the caller provides: the serial number, validity, issuer key, and
+//! the URIs pointing to the issuer.
+
+use std::path::Path;
+use bcder::Oid;
+use bytes::Bytes;
+use mkrpki::{CaCertBuilder, Error};
+use mkrpki::sigobj::SignedObjectBuilder;
+use rpki::crypto::Signer;
+use rpki::repository::cert::Cert;
+use rpki::repository::sigobj::SignedObject;
+use rpki::repository::x509::{Serial, Validity};
+use rpki::uri;
+
+
+//------------ Decoding ------------------------------------------------------
+
+/// Decodes a certificate read from a file.
+pub fn decode_cert(path: &Path, data: Bytes) -> Result<Cert, Error> {
+    Cert::decode(data).map_err(|err| {
+        Error::Encoding(format!(
+            "Failed to decode certificate {}: {}", path.display(), err
+        ))
+    })
+}
+
+/// Decodes a signed object read from a file.
+pub fn decode_object(path: &Path, data: Bytes) -> Result<SignedObject, Error> {
+    SignedObject::decode(data, false).map_err(|err| {
+        Error::Encoding(format!(
+            "Failed to decode signed object {}: {}", path.display(), err
+        ))
+    })
+}
+
+
+//------------ Re-issuing ----------------------------------------------------
+
+/// Re-issues a CA certificate.
+///
+/// The subject key, the subject information access, and the resources are
+/// taken from `cert` which was loaded from `path`. Fails if `cert` isn’t a
+/// CA certificate.
+#[allow(clippy::too_many_arguments)]
+pub fn reissue_ca_cert<S: Signer>(
+    path: &Path,
+    cert: &Cert,
+    serial: Serial,
+    validity: Validity,
+    crl_uri: uri::Rsync,
+    ca_issuer: uri::Rsync,
+    signer: &S,
+    issuer_key: &S::KeyId,
+) -> Result<Bytes, Error> {
+    let (ca_repository, rpki_manifest) = match (
+        cert.ca_repository(), cert.rpki_manifest()
+    ) {
+        (Some(repository), Some(manifest)) if cert.is_ca() => {
+            (repository.clone(), manifest.clone())
+        }
+        _ => {
+            return Err(Error::Usage(format!(
+                "Cannot re-issue {}: not a CA certificate.", path.display()
+            )))
+        }
+    };
+    let mut res = CaCertBuilder::new(
+        serial, validity, cert.subject_public_key_info().clone(),
+        crl_uri, ca_issuer, ca_repository, rpki_manifest
+    );
+    res.set_rpki_notify(cert.rpki_notify().cloned());
+    res.set_v4_resources(cert.v4_resources().clone());
+    res.set_v6_resources(cert.v6_resources().clone());
+    res.set_as_resources(cert.as_resources().clone());
+    res.build(signer, issuer_key)
+}
+
+/// Re-signs a signed object.
+///
+/// The EE certificate is described by `sigobj`. Its resources are taken
+/// from the EE certificate of `object`, as are the content type and
+/// content.
+pub fn resign_object<S: Signer>(
+    object: &SignedObject,
+    mut sigobj: SignedObjectBuilder,
+    signer: &S,
+    issuer_key: &S::KeyId,
+) -> Result<Bytes, Error> {
+    let ee = object.cert();
+    sigobj.set_v4_resources(ee.v4_resources().clone());
+    sigobj.set_v6_resources(ee.v6_resources().clone());
+    sigobj.set_as_resources(ee.as_resources().clone());
+    let content = object.content().to_bytes();
+    Ok(sigobj.finalize(
+        Oid(object.content_type().0.as_ref()), content.as_ref(),
+        signer, issuer_key
+    )?.into_bytes())
+}
@@ -0,0 +1,69 @@
+//! Building route origin authorizations.
+
+use std::net::IpAddr;
+use bcder::Mode;
+use bcder::encode::Values;
+use bytes::Bytes;
+use rpki::crypto::Signer;
+use rpki::oid;
+use rpki::repository::resources::Asn;
+use rpki::repository::roa;
+use rpki::repository::roa::RoaIpAddress;
+use crate::error::Error;
+use crate::sigobj::SignedObjectBuilder;
+
+
+//------------ RoaBuilder ----------------------------------------------------
+
+/// A builder for a route origin authorization.
+pub struct RoaBuilder {
+    /// The builder for the content of the ROA.
+    roa: roa::RoaBuilder,
+}
+
+impl RoaBuilder {
+    pub fn new(asn: Asn) -> Self {
+        RoaBuilder { roa: roa::RoaBuilder::new(asn) }
+    }
+
+    /// Adds an IPv4 prefix.
+    pub fn push_v4(&mut self, addr: RoaIpAddress) {
+        self.roa.push_v4(addr)
+    }
+
+    /// Adds an IPv6 prefix.
+    pub fn push_v6(&mut self, addr: RoaIpAddress) {
+        self.roa.push_v6(addr)
+    }
+
+    /// Adds a prefix with an optional max length.
+    pub fn push_addr(&mut self, addr: IpAddr, len: u8, max_len: Option<u8>) {
+        self.roa.push_addr(addr, len, max_len)
+    }
+
+    /// Signs the ROA with the issuer’s key and returns its encoding.
+    ///
+    /// Unless they have been set explicitly in `sigobj`, the IP resources
+    /// of the EE certificate are those covered by the prefixes.
+    pub fn build<S: Signer>(
+        self,
+        mut sigobj: SignedObjectBuilder,
+        signer: &S,
+        issuer_key: &S::KeyId,
+    ) -> Result<Bytes, Error> {
+        let v4 = self.roa.v4().to_resources();
+        let v6 = self.roa.v6().to_resources();
+        let content = self.roa.to_attestation().encode_ref().to_captured(
+            Mode::Der
+        );
+        if !sigobj.v4_resources().is_present() {
+            sigobj.set_v4_resources(v4);
+        }
+        if !sigobj.v6_resources().is_present() {
+            sigobj.set_v6_resources(v6);
+        }
+        Ok(sigobj.finalize(
+            oid::ROUTE_ORIGIN_AUTHZ, content.as_slice(), signer, issuer_key
+        )?.into_bytes())
+    }
+}
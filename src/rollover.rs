@@ -0,0 +1,675 @@
+//! Rolling the key of a CA.
+//!
+//! The key roll follows RFC 6489 and happens in three stages. Staging
+//! creates a new key, has the parent issue a certificate for it, and
+//! publishes a CRL and manifest for the new key at a new publication point
+//! next to the current one. Activating re-signs all objects of the current
+//! publication point with the new key and publishes them at the new one.
+//! Retiring has the parent revoke the certificate of the old key after
+//! which the new key becomes the current key.
+//!
+//! If the publication point of the parent CA is given, staging and retiring
+//! also re-issue the parent’s manifest so that it covers the new
+//! certificate and CRL.
+//!
+//! Between the stages, the old and new keys are kept in the state of the
+//! CA.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use bytes::Bytes;
+use mkrpki::Error;
+use mkrpki::sigobj::SignedObjectBuilder;
+use rpki::repository::resources::{
+    AsBlock, AsResources, IpBlock, IpResources
+};
+use rpki::repository::x509::Serial;
+use rpki::uri;
+use structopt::StructOpt;
+use unwrap::unwrap;
+use crate::clock;
+use crate::clock::Period;
+use crate::glob::Glob;
+use crate::issuer::{IssuerCert, resolve_uri};
+use crate::reissue::{
+    decode_cert, decode_object, reissue_ca_cert, resign_object
+};
+use crate::state::{CaKey, CaState, Counter, RollStage, take_counter};
+use super::{
+    create_signer, load_file, publishable_files, save_file, Cert, Crl,
+    Format, Key, KeyAlgorithm, Mft
+};
+
+
+//------------ Rollover ------------------------------------------------------
+
+#[derive(StructOpt)]
+#[allow(clippy::large_enum_variant)]
+pub enum Rollover {
+    /// Creates a new key and publishes it at a new publication point.
+    #[structopt(name="stage")]
+    Stage(Stage),
+
+    /// Re-signs all objects with the new key.
+    #[structopt(name="activate")]
+    Activate(Activate),
+
+    /// Revokes the certificate of the old key.
+    #[structopt(name="retire")]
+    Retire(Retire),
+}
+
+impl Rollover {
+    pub fn run(self) -> Result<(), Error> {
+        match self {
+            Rollover::Stage(stage) => stage.run(),
+            Rollover::Activate(activate) => activate.run(),
+            Rollover::Retire(retire) => retire.run(),
+        }
+    }
+}
+
+
+//------------ Stage ---------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Stage {
+    /// Path to the directory with the state of the CA.
+    #[structopt(long="state")]
+    state: PathBuf,
+
+    /// Path to the current private key. Taken from the state if missing.
+    #[structopt(long="key")]
+    key: Option<PathBuf>,
+
+    /// Path to the current CA certificate. Taken from the state if missing.
+    #[structopt(long="cert")]
+    cert: Option<PathBuf>,
+
+    /// Path to the current publication point. Taken from the state if
+    /// missing.
+    #[structopt(long="repository")]
+    repository: Option<PathBuf>,
+
+    /// Path to the private key of the parent CA.
+    #[structopt(long="issuer-key")]
+    issuer_key: PathBuf,
+
+    /// Path to the certificate of the parent CA to derive URIs from. Must
+    /// have the file name the certificate is published under.
+    #[structopt(long="issuer-cert")]
+    issuer_cert: Option<PathBuf>,
+
+    /// Path to the directory with the state of the parent CA.
+    #[structopt(long="issuer-state")]
+    issuer_state: Option<PathBuf>,
+
+    /// Path to the publication point of the parent CA. If given, the
+    /// parent’s manifest is re-issued.
+    #[structopt(long="issuer-repository")]
+    issuer_repository: Option<PathBuf>,
+
+    /// Serial number of the new certificate. Taken from the issuer state
+    /// if missing.
+    #[structopt(long="serial")]
+    serial: Option<Serial>,
+
+    /// RPKI URI of the parent’s CRL. Derived from the issuer certificate if
+    /// missing.
+    #[structopt(long="crl")]
+    crl_uri: Option<uri::Rsync>,
+
+    /// URI of the parent’s certificate. Derived from the issuer certificate
+    /// if missing.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: Option<uri::Rsync>,
+
+    /// CA repository URI of the new publication point.
+    #[structopt(long="new-ca-repository")]
+    new_ca_repository: uri::Rsync,
+
+    /// Path to the directory of the new publication point.
+    #[structopt(long="new-repository")]
+    new_repository: PathBuf,
+
+    /// Path to file to write the certificate for the new key into.
+    #[structopt(long="new-cert")]
+    new_cert: PathBuf,
+
+    /// How long the new objects are valid, in days if just a number.
+    #[structopt(long="days", default_value="30")]
+    days: Period,
+}
+
+impl Stage {
+    pub fn run(self) -> Result<(), Error> {
+        let mut state = CaState::load(&self.state)?;
+        if state.key_roll().is_some() {
+            return Err(Error::Usage(format!(
+                "A key roll is already in progress for {}.",
+                self.state.display()
+            )))
+        }
+        let current = match (&self.key, &self.cert, &self.repository) {
+            (Some(key), Some(cert), Some(repository)) => {
+                CaKey::new(key, cert, repository)
+            }
+            (None, None, None) => {
+                match state.key() {
+                    Some(key) => key.clone(),
+                    None => {
+                        return Err(Error::Usage(
+                            "Either --key, --cert, and --repository or a \
+                             state with a current key must be given.".into()
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::Usage(
+                    "--key, --cert, and --repository must be given \
+                     together.".into()
+                ))
+            }
+        };
+
+        // Determine the parent’s URIs before creating the new key so that
+        // a missing URI doesn’t leave the key behind.
+        let issuer_cert = IssuerCert::load_opt(self.issuer_cert.as_deref())?;
+        let crl_uri = resolve_uri(
+            self.crl_uri.clone(), issuer_cert.as_ref(), "--crl",
+            IssuerCert::crl_uri
+        )?;
+        let ca_issuer = resolve_uri(
+            self.ca_issuer.clone(), issuer_cert.as_ref(), "--ca-issuer",
+            IssuerCert::ca_issuer
+        )?;
+        let parent = match self.issuer_repository {
+            Some(ref repository) => {
+                match (&self.issuer_cert, &self.issuer_state) {
+                    (Some(cert), Some(state)) => Some(Parent {
+                        key: self.issuer_key.clone(),
+                        cert: cert.clone(),
+                        state: state.clone(),
+                        repository: repository.clone(),
+                        crl_uri: Some(crl_uri.clone()),
+                        ca_issuer: Some(ca_issuer.clone()),
+                    }),
+                    _ => {
+                        return Err(Error::Usage(
+                            "--issuer-repository requires --issuer-cert and \
+                             --issuer-state.".into()
+                        ))
+                    }
+                }
+            }
+            None => None
+        };
+        let current_cert = IssuerCert::load(current.cert())?;
+        let current_cert = current_cert.cert();
+        let rpki_manifest = match current_cert.rpki_manifest() {
+            Some(uri) => join(&self.new_ca_repository, uri_file_name(uri))?,
+            None => {
+                return Err(Error::Encoding(format!(
+                    "CA certificate {} has no rpkiManifest URI.",
+                    current.cert().display()
+                )))
+            }
+        };
+
+        // Name the new key after the new certificate.
+        let stem = match self.new_cert.file_stem().and_then(OsStr::to_str) {
+            Some(stem) => stem,
+            None => {
+                return Err(Error::Usage(format!(
+                    "Invalid file name {}.", self.new_cert.display()
+                )))
+            }
+        };
+        let keys = self.state.join("keys");
+        create_dir(&keys)?;
+        let private = keys.join(format!("{}.key", stem));
+        let public = keys.join(format!("{}.pub", stem));
+        if private.exists() {
+            return Err(Error::Usage(format!(
+                "Key {} already exists.", private.display()
+            )))
+        }
+        Key {
+            private: private.clone(),
+            public: public.clone(),
+            algorithm: KeyAlgorithm::Rsa,
+            format: Format::Der,
+        }.run()?;
+
+        let (v4_resources, inherit_v4) = ip_blocks(
+            current_cert.v4_resources()
+        );
+        let (v6_resources, inherit_v6) = ip_blocks(
+            current_cert.v6_resources()
+        );
+        let (as_resources, inherit_as) = as_blocks(
+            current_cert.as_resources()
+        );
+        let res = Cert {
+            issuer_key: self.issuer_key,
+            issuer_cert: self.issuer_cert,
+            subject_key: public.clone(),
+            serial: self.serial,
+            not_before: None,
+            not_after: None,
+            valid_days: Some(self.days),
+            trim_resources: false,
+            trim_overclaim: false,
+            allow_overclaim: false,
+            crl_uri: Some(crl_uri),
+            ca_issuer: Some(ca_issuer),
+            ca_repository: self.new_ca_repository,
+            rpki_manifest,
+            rpki_notify: current_cert.rpki_notify().cloned(),
+            v4_resources,
+            inherit_v4,
+            v6_resources,
+            inherit_v6,
+            as_resources,
+            inherit_as,
+            state: self.issuer_state,
+            corrupt: None,
+            format: Format::Der,
+            output: self.new_cert.clone(),
+        }.run();
+        if let Err(err) = res {
+            // Drop the new key again so the stage can be retried.
+            let _ = fs::remove_file(&private);
+            let _ = fs::remove_file(&public);
+            return Err(err)
+        }
+        if let Some(parent) = parent {
+            parent.publish(self.days)?;
+        }
+
+        let new = CaKey::new(&private, &self.new_cert, &self.new_repository);
+        create_dir(new.repository())?;
+        publish(&mut state, &new, self.days)?;
+        state.stage_key(current, new);
+        state.save()
+    }
+}
+
+
+//------------ Activate ------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Activate {
+    /// Path to the directory with the state of the CA.
+    #[structopt(long="state")]
+    state: PathBuf,
+
+    /// How long the new CRL and manifest are valid, in days if just a
+    /// number.
+    #[structopt(long="days", default_value="30")]
+    days: Period,
+}
+
+impl Activate {
+    pub fn run(self) -> Result<(), Error> {
+        let mut state = CaState::load(&self.state)?;
+        let (old, new) = match (state.key(), state.key_roll()) {
+            (Some(old), Some(roll)) if roll.stage() == RollStage::Staged => {
+                (old.clone(), roll.new_key().clone())
+            }
+            (_, Some(_)) => {
+                return Err(Error::Usage(
+                    "The new key has already been activated.".into()
+                ))
+            }
+            _ => {
+                return Err(Error::Usage(format!(
+                    "No key roll in progress for {}.", self.state.display()
+                )))
+            }
+        };
+        let (signer, key) = create_signer(new.key())?;
+        let issuer = IssuerCert::load(new.cert())?;
+        let crl_uri = issuer.crl_uri()?;
+        let ca_issuer = issuer.ca_issuer()?;
+        state.activate_key();
+
+        // The old manifest isn’t publishable and the old CRL is excluded,
+        // so there are only the objects to re-sign left.
+        let exclude = [unwrap!(Glob::from_str("*.crl"))];
+        let files = publishable_files(
+            old.repository(), &exclude, old.repository()
+        )?;
+        for path in files {
+            let name = match path.file_name().and_then(OsStr::to_str) {
+                Some(name) => name,
+                None => continue
+            };
+            let output = new.repository().join(name);
+            let data = Bytes::from(load_file(&path)?);
+            let serial = take_counter(
+                Some(&mut state), Counter::Serial, None
+            )?;
+            let (data, not_after) = if name.ends_with(".cer") {
+                let cert = decode_cert(&path, data)?;
+                let validity = cert.validity();
+                (
+                    reissue_ca_cert(
+                        &path, &cert, serial, validity, crl_uri.clone(),
+                        ca_issuer.clone(), &signer, &key
+                    )?,
+                    validity.not_after()
+                )
+            }
+            else {
+                let object = decode_object(&path, data)?;
+                let ee = object.cert();
+                let validity = ee.validity();
+                let signed_object = match ee.signed_object() {
+                    Some(_) => Some(issuer.signed_object(&output)?),
+                    None => None
+                };
+                let sigobj = SignedObjectBuilder::new(
+                    serial, validity, crl_uri.clone(), ca_issuer.clone(),
+                    signed_object
+                );
+                (
+                    resign_object(&object, sigobj, &signer, &key)?,
+                    validity.not_after()
+                )
+            };
+            save_file(&output, &data)?;
+            eprintln!("Re-signed: {}", output.display());
+            let kind = path.extension().and_then(OsStr::to_str).unwrap_or("");
+            state.issue(serial, kind, &output, not_after);
+        }
+
+        publish(&mut state, &new, self.days)?;
+        state.save()
+    }
+}
+
+
+//------------ Retire --------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Retire {
+    /// Path to the directory with the state of the CA.
+    #[structopt(long="state")]
+    state: PathBuf,
+
+    /// Path to the private key of the parent CA.
+    #[structopt(long="issuer-key")]
+    issuer_key: PathBuf,
+
+    /// Path to the directory with the state of the parent CA.
+    #[structopt(long="issuer-state")]
+    issuer_state: PathBuf,
+
+    /// Path to file to write the parent’s new CRL into.
+    #[structopt(long="issuer-crl")]
+    issuer_crl: PathBuf,
+
+    /// Path to the certificate of the parent CA. Must have the file name
+    /// the certificate is published under.
+    #[structopt(long="issuer-cert")]
+    issuer_cert: Option<PathBuf>,
+
+    /// Path to the publication point of the parent CA. If given, the
+    /// parent’s manifest is re-issued.
+    #[structopt(long="issuer-repository")]
+    issuer_repository: Option<PathBuf>,
+
+    /// URI of the parent’s certificate. Derived from the issuer certificate
+    /// if missing.
+    #[structopt(long="ca-issuer")]
+    ca_issuer: Option<uri::Rsync>,
+
+    /// Time until the next update of the parent’s CRL and manifest, in days
+    /// if just a number.
+    #[structopt(long="days", default_value="30")]
+    days: Period,
+}
+
+impl Retire {
+    pub fn run(self) -> Result<(), Error> {
+        let mut state = CaState::load(&self.state)?;
+        let old = match (state.key(), state.key_roll()) {
+            (Some(old), Some(roll)) if roll.stage() == RollStage::Active => {
+                old.clone()
+            }
+            (_, Some(_)) => {
+                return Err(Error::Usage(
+                    "The new key has to be activated first.".into()
+                ))
+            }
+            _ => {
+                return Err(Error::Usage(format!(
+                    "No key roll in progress for {}.", self.state.display()
+                )))
+            }
+        };
+        let parent = match (self.issuer_repository, self.issuer_cert) {
+            (Some(repository), Some(cert)) => Some(Parent {
+                key: self.issuer_key.clone(),
+                cert,
+                state: self.issuer_state.clone(),
+                repository,
+                crl_uri: None,
+                ca_issuer: self.ca_issuer,
+            }),
+            (Some(_), None) => {
+                return Err(Error::Usage(
+                    "--issuer-repository requires --issuer-cert.".into()
+                ))
+            }
+            (None, _) => None
+        };
+        let old_cert = IssuerCert::load(old.cert())?;
+        Crl {
+            issuer_key: self.issuer_key,
+            this_update: None,
+            next_update: None,
+            next_days: Some(self.days),
+            revoked_certs: Vec::new(),
+            revoke: vec![old_cert.cert().serial_number()],
+            crl_number: None,
+            state: Some(self.issuer_state),
+            corrupt: None,
+            format: Format::Der,
+            output: self.issuer_crl,
+        }.run()?;
+        if let Some(parent) = parent {
+            parent.withdraw(old.cert())?;
+            parent.publish(self.days)?;
+        }
+        state.retire_key();
+        state.save()
+    }
+}
+
+
+//------------ Parent --------------------------------------------------------
+
+/// The parent CA whose manifest needs to be re-issued.
+struct Parent {
+    /// Path to the private key of the parent CA.
+    key: PathBuf,
+
+    /// Path to the certificate of the parent CA.
+    cert: PathBuf,
+
+    /// Path to the directory with the state of the parent CA.
+    state: PathBuf,
+
+    /// Path to the publication point of the parent CA.
+    repository: PathBuf,
+
+    /// The URI of the parent’s CRL if given explicitly.
+    crl_uri: Option<uri::Rsync>,
+
+    /// The URI of the parent’s certificate if given explicitly.
+    ca_issuer: Option<uri::Rsync>,
+}
+
+impl Parent {
+    /// Removes a certificate from the parent’s publication point.
+    ///
+    /// The certificate is expected under the file name of `cert`. If there
+    /// is no such file, nothing happens.
+    fn withdraw(&self, cert: &Path) -> Result<(), Error> {
+        let name = match cert.file_name() {
+            Some(name) => name,
+            None => return Ok(())
+        };
+        let path = self.repository.join(name);
+        if !path.exists() {
+            return Ok(())
+        }
+        fs::remove_file(&path).map_err(|err| {
+            Error::Io(format!(
+                "Failed to remove {}: {}", path.display(), err
+            ))
+        })?;
+        eprintln!("Removed: {}", path.display());
+        Ok(())
+    }
+
+    /// Re-issues the manifest of the parent CA.
+    ///
+    /// The manifest lists all publishable objects of the parent’s
+    /// publication point.
+    fn publish(self, days: Period) -> Result<(), Error> {
+        let issuer = IssuerCert::load(&self.cert)?;
+        let now = clock::now();
+        let next = now + days.duration();
+        let mft = self.repository.join(
+            uri_file_name(&issuer.rpki_manifest()?)
+        );
+        Mft {
+            issuer_key: self.key,
+            issuer_cert: Some(self.cert),
+            serial: None,
+            not_before: Some(now.into()),
+            not_after: Some(next.into()),
+            valid_days: None,
+            crl_uri: self.crl_uri,
+            ca_issuer: self.ca_issuer,
+            number: None,
+            signed_object: None,
+            this_update: Some(now.into()),
+            next_update: Some(next.into()),
+            next_days: None,
+            files: Vec::new(),
+            dir: Some(self.repository),
+            exclude: Vec::new(),
+            state: Some(self.state),
+            corrupt: None,
+            output: mft,
+        }.run()
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Publishes a new CRL and manifest for a key.
+///
+/// The CRL is empty since the key hasn’t revoked anything yet. The manifest
+/// lists all publishable objects of the key’s publication point.
+fn publish(
+    state: &mut CaState, key: &CaKey, days: Period
+) -> Result<(), Error> {
+    let issuer = IssuerCert::load(key.cert())?;
+    let now = clock::now();
+    let next = now + days.duration();
+
+    let crl = key.repository().join(uri_file_name(&issuer.crl_uri()?));
+    Crl {
+        issuer_key: key.key().into(),
+        this_update: Some(now.into()),
+        next_update: Some(next.into()),
+        next_days: None,
+        revoked_certs: Vec::new(),
+        revoke: Vec::new(),
+        crl_number: Some(
+            take_counter(Some(state), Counter::CrlNumber, None)?
+        ),
+        state: None,
+        corrupt: None,
+        format: Format::Der,
+        output: crl,
+    }.run()?;
+
+    let mft = key.repository().join(uri_file_name(&issuer.rpki_manifest()?));
+    let serial = take_counter(Some(state), Counter::Serial, None)?;
+    Mft {
+        issuer_key: key.key().into(),
+        issuer_cert: Some(key.cert().into()),
+        serial: Some(serial),
+        not_before: Some(now.into()),
+        not_after: Some(next.into()),
+        valid_days: None,
+        crl_uri: None,
+        ca_issuer: None,
+        number: Some(
+            take_counter(Some(state), Counter::ManifestNumber, None)?
+        ),
+        signed_object: None,
+        this_update: Some(now.into()),
+        next_update: Some(next.into()),
+        next_days: None,
+        files: Vec::new(),
+        dir: Some(key.repository().into()),
+        exclude: Vec::new(),
+        state: None,
+        corrupt: None,
+        output: mft.clone(),
+    }.run()?;
+    state.issue(serial, "mft", &mft, next);
+    Ok(())
+}
+
+/// Converts IP resources into blocks for the `cer` command.
+///
+/// Returns the blocks and whether the resources are inherited.
+fn ip_blocks(resources: &IpResources) -> (Vec<IpBlock>, bool) {
+    match resources.to_blocks() {
+        Ok(blocks) => (blocks.iter().collect(), false),
+        Err(_) => (Vec::new(), true)
+    }
+}
+
+/// Converts AS resources into blocks for the `cer` command.
+///
+/// Returns the blocks and whether the resources are inherited.
+fn as_blocks(resources: &AsResources) -> (Vec<AsBlock>, bool) {
+    match resources.to_blocks() {
+        Ok(blocks) => (blocks.iter().collect(), false),
+        Err(_) => (Vec::new(), true)
+    }
+}
+
+/// Returns the last path segment of a URI.
+fn uri_file_name(uri: &uri::Rsync) -> &str {
+    uri.path().rsplit('/').next().unwrap_or("")
+}
+
+/// Adds a file name to a directory URI.
+fn join(dir: &uri::Rsync, name: &str) -> Result<uri::Rsync, Error> {
+    dir.join(name.as_bytes()).map_err(|_| {
+        Error::Usage(format!("Invalid file name {} for URI.", name))
+    })
+}
+
+/// Creates a directory and all its parents.
+fn create_dir(path: &Path) -> Result<(), Error> {
+    fs::create_dir_all(path).map_err(|err| {
+        Error::Io(format!(
+            "Failed to create directory {}: {}", path.display(), err
+        ))
+    })
+}
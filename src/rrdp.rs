@@ -0,0 +1,347 @@
+//! Creating RRDP files for a repository.
+//!
+//! This takes a directory in rsync layout, i.e., with the host name and
+//! module as the first two levels, and produces RRDP notification, snapshot
+//! and delta files as described in RFC 8182 that can be served by any
+//! static HTTP server.
+//!
+//! The output directory doubles as the state of the RRDP server. If it
+//! already contains a notification file, the new snapshot becomes the next
+//! serial of the same session and a delta against the previous snapshot is
+//! added.
+
+use std::fs;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use bytes::Bytes;
+use mkrpki::Error;
+use rpki::rrdp::{
+    DeltaElement, DeltaInfo, Delta, Hash, NotificationFile, PublishElement,
+    Snapshot, UpdateElement, UriAndHash, WithdrawElement
+};
+use rpki::uri;
+use structopt::StructOpt;
+use uuid::Uuid;
+use crate::seeded;
+use super::{load_file, save_file};
+
+
+/// The objects of a repository keyed by their rsync URI.
+type Objects = HashMap<uri::Rsync, Bytes>;
+
+
+//------------ Rrdp ----------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Rrdp {
+    /// Path to the directory with the repository in rsync layout.
+    #[structopt(long="repository")]
+    repository: PathBuf,
+
+    /// The HTTPS URI of the directory the RRDP files are served from.
+    #[structopt(long="rrdp-base")]
+    rrdp_base: uri::Https,
+
+    /// Path to the directory to write the RRDP files into.
+    #[structopt(long="output")]
+    output: PathBuf,
+
+    /// Start a new session even if there is a previous state.
+    #[structopt(long="new-session")]
+    new_session: bool,
+
+    /// The maximum number of deltas to list in the notification file.
+    #[structopt(long="max-deltas", default_value="20")]
+    max_deltas: usize,
+}
+
+impl Rrdp {
+    pub fn run(self) -> Result<(), Error> {
+        if !self.rrdp_base.as_str().ends_with('/') {
+            return Err(Error::Usage(
+                "The RRDP base URI must end in a slash.".into()
+            ))
+        }
+        let current = self.load_repository()?;
+        let notify_path = self.output.join("notification.xml");
+
+        let previous = if !self.new_session && notify_path.exists() {
+            Some(self.load_previous(&notify_path)?)
+        }
+        else {
+            None
+        };
+
+        let (session_id, serial, mut deltas) = match previous {
+            Some((notify, snapshot)) => {
+                let elements = delta_elements(&snapshot, &current);
+                if elements.is_empty() {
+                    eprintln!("No changes since serial {}.", notify.serial());
+                    return Ok(())
+                }
+                let serial = notify.serial() + 1;
+                let delta = Delta::new(
+                    notify.session_id(), serial, elements
+                );
+                let mut xml = Vec::new();
+                if let Err(err) = delta.write_xml(&mut xml) {
+                    return Err(Error::Encoding(format!(
+                        "Failed to create delta: {}", err
+                    )))
+                }
+                let uri = self.write_file(
+                    notify.session_id(), serial, "delta.xml", &xml
+                )?;
+                let mut deltas = vec![
+                    DeltaInfo::new(serial, uri, Hash::from_data(&xml))
+                ];
+                deltas.extend(notify.deltas().iter().cloned());
+                deltas.truncate(self.max_deltas);
+                (notify.session_id(), serial, deltas)
+            }
+            None => (new_session_id(), 1, Vec::new())
+        };
+        deltas.sort_by_key(|delta| std::cmp::Reverse(delta.serial()));
+
+        let mut elements: Vec<_> = current.into_iter().map(|(uri, data)| {
+            PublishElement::new(uri, data)
+        }).collect();
+        elements.sort_by(|left, right| {
+            left.uri().as_str().cmp(right.uri().as_str())
+        });
+        let snapshot = Snapshot::new(session_id, serial, elements);
+        let mut xml = Vec::new();
+        if let Err(err) = snapshot.write_xml(&mut xml) {
+            return Err(Error::Encoding(format!(
+                "Failed to create snapshot: {}", err
+            )))
+        }
+        let uri = self.write_file(session_id, serial, "snapshot.xml", &xml)?;
+
+        let notify = NotificationFile::new(
+            session_id, serial,
+            UriAndHash::new(uri, Hash::from_data(&xml)),
+            deltas
+        );
+        let mut xml = Vec::new();
+        if let Err(err) = notify.write_xml(&mut xml) {
+            return Err(Error::Encoding(format!(
+                "Failed to create notification file: {}", err
+            )))
+        }
+        save_file(&notify_path, &xml)?;
+        eprintln!("Notification: {}", notify_path.display());
+        eprintln!("Session {}, serial {}.", session_id, serial);
+        Ok(())
+    }
+
+    /// Loads all objects of the rsync repository.
+    fn load_repository(&self) -> Result<Objects, Error> {
+        let mut res = HashMap::new();
+        for host in read_dir(&self.repository)? {
+            for module in read_dir(&host)? {
+                let base = format!(
+                    "rsync://{}/{}/", file_name(&host)?, file_name(&module)?
+                );
+                load_tree(&module, &base, &mut res)?;
+            }
+        }
+        Ok(res)
+    }
+
+    /// Loads the previous notification file and its snapshot.
+    fn load_previous(
+        &self, path: &Path
+    ) -> Result<(NotificationFile, Objects), Error> {
+        let data = load_file(path)?;
+        let notify = match NotificationFile::parse(data.as_slice()) {
+            Ok(notify) => notify,
+            Err(err) => {
+                return Err(Error::Encoding(format!(
+                    "Invalid notification file {}: {}", path.display(), err
+                )))
+            }
+        };
+        let snapshot_path = self.uri_to_path(notify.snapshot().uri())?;
+        let data = load_file(&snapshot_path)?;
+        if !notify.snapshot().hash().matches(&data) {
+            return Err(Error::Encoding(format!(
+                "Snapshot {} does not match its hash.", snapshot_path.display()
+            )))
+        }
+        let snapshot = match Snapshot::parse(data.as_slice()) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                return Err(Error::Encoding(format!(
+                    "Invalid snapshot {}: {}", snapshot_path.display(), err
+                )))
+            }
+        };
+        if snapshot.session_id() != notify.session_id()
+            || snapshot.serial() != notify.serial()
+        {
+            return Err(Error::Encoding(format!(
+                "Snapshot {} does not match the notification file.",
+                snapshot_path.display()
+            )))
+        }
+        let elements = snapshot.into_elements().into_iter().map(|item| {
+            item.unpack()
+        }).collect();
+        Ok((notify, elements))
+    }
+
+    /// Writes a file for the given session and serial.
+    ///
+    /// Returns the URI of the file.
+    fn write_file(
+        &self, session_id: Uuid, serial: u64, name: &str, data: &[u8]
+    ) -> Result<uri::Https, Error> {
+        let rel = format!("{}/{}/{}", session_id, serial, name);
+        let uri = match self.rrdp_base.join(rel.as_bytes()) {
+            Ok(uri) => uri,
+            Err(err) => {
+                return Err(Error::Usage(format!(
+                    "Invalid RRDP URI for {}: {}", rel, err
+                )))
+            }
+        };
+        let path = self.output.join(&rel);
+        if let Some(parent) = path.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                return Err(Error::Io(format!(
+                    "Failed to create directory {}: {}", parent.display(), err
+                )))
+            }
+        }
+        save_file(&path, data)?;
+        eprintln!("{}: {}", name, path.display());
+        Ok(uri)
+    }
+
+    /// Returns the path in the output directory for an RRDP URI.
+    fn uri_to_path(&self, uri: &uri::Https) -> Result<PathBuf, Error> {
+        match uri.as_str().strip_prefix(self.rrdp_base.as_str()) {
+            Some(rel) => Ok(self.output.join(rel)),
+            None => {
+                Err(Error::Usage(format!(
+                    "URI {} is not under the RRDP base URI {}.",
+                    uri, self.rrdp_base
+                )))
+            }
+        }
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Returns the ID for a new session.
+///
+/// In deterministic mode, the ID is derived from the seed.
+fn new_session_id() -> Uuid {
+    let mut bytes = [0u8; 16];
+    if seeded::fill(&mut bytes) {
+        uuid::Builder::from_random_bytes(bytes).into_uuid()
+    }
+    else {
+        Uuid::new_v4()
+    }
+}
+
+/// Returns the elements of a delta from the old to the new objects.
+fn delta_elements(
+    old: &Objects,
+    new: &Objects,
+) -> Vec<DeltaElement> {
+    let mut res = Vec::new();
+    for (uri, data) in sorted(new) {
+        match old.get(uri) {
+            Some(old_data) if old_data == data => { }
+            Some(old_data) => {
+                res.push(UpdateElement::new(
+                    uri.clone(), Hash::from_data(old_data), data.clone()
+                ).into())
+            }
+            None => {
+                res.push(PublishElement::new(uri.clone(), data.clone()).into())
+            }
+        }
+    }
+    for (uri, data) in sorted(old) {
+        if !new.contains_key(uri) {
+            res.push(
+                WithdrawElement::new(uri.clone(), Hash::from_data(data)).into()
+            )
+        }
+    }
+    res
+}
+
+/// Returns the objects sorted by their URI.
+fn sorted(objects: &Objects) -> Vec<(&uri::Rsync, &Bytes)> {
+    let mut res: Vec<_> = objects.iter().collect();
+    res.sort_by(|left, right| left.0.as_str().cmp(right.0.as_str()));
+    res
+}
+
+/// Loads all files below a directory.
+fn load_tree(
+    dir: &Path, base: &str, res: &mut Objects
+) -> Result<(), Error> {
+    for path in read_dir(dir)? {
+        let name = file_name(&path)?;
+        if path.is_dir() {
+            load_tree(&path, &format!("{}{}/", base, name), res)?;
+        }
+        else {
+            let uri = format!("{}{}", base, name);
+            let uri = match uri::Rsync::from_string(uri.clone()) {
+                Ok(uri) => uri,
+                Err(err) => {
+                    return Err(Error::Usage(format!(
+                        "Invalid rsync URI {}: {}", uri, err
+                    )))
+                }
+            };
+            res.insert(uri, load_file(&path)?.into());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the sorted paths of all entries of a directory.
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return Err(Error::Io(format!(
+                "Failed to read directory {}: {}", dir.display(), err
+            )))
+        }
+    };
+    let mut res = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => res.push(entry.path()),
+            Err(err) => {
+                return Err(Error::Io(format!(
+                    "Failed to read directory {}: {}", dir.display(), err
+                )))
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Returns the file name of a path as a string.
+fn file_name(path: &Path) -> Result<&str, Error> {
+    match path.file_name().and_then(OsStr::to_str) {
+        Some(name) => Ok(name),
+        None => {
+            Err(Error::Usage(format!("Illegal file name {}.", path.display())))
+        }
+    }
+}
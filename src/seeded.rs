@@ -0,0 +1,242 @@
+//! Randomness derived from a seed.
+//!
+//! In deterministic mode, everything that would normally be random – keys,
+//! one-off EE keys of signed objects, random serial numbers, and the RRDP
+//! session ID – is taken from random number generators seeded with the
+//! value of the `--seed` option and a label describing the invocation.
+//! Running the same commands with the same seed thus produces the same
+//! output while different commands produce different values.
+//!
+//! There are separate streams for long-lived keys, one-off keys, and
+//! everything else, so that a one-off key never repeats a long-lived key.
+//! The key of each stream is SHA-256 over the seed, the label, and the
+//! stream’s purpose. The stream itself is SHA-256 over this key and a block
+//! counter. This is not suitable for keys that need to be secret, but that
+//! isn’t the point of deterministic mode.
+
+use std::io;
+use std::sync::Mutex;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroupRef, EcKey, EcPoint};
+use openssl::error::ErrorStack;
+use openssl::pkey::Private;
+use openssl::rsa::Rsa;
+use openssl::sha::Sha256;
+
+
+//------------ Global Generator ----------------------------------------------
+
+/// The generators if deterministic mode has been enabled.
+static GENERATOR: Mutex<Option<Generator>> = Mutex::new(None);
+
+/// Enables deterministic mode using the given seed and invocation label.
+pub fn enable(seed: u64, label: &str) {
+    *lock() = Some(Generator::new(seed, label));
+}
+
+/// Fills `target` with seeded random data.
+///
+/// Returns `false` and leaves `target` untouched if deterministic mode is
+/// not enabled.
+pub fn fill(target: &mut [u8]) -> bool {
+    match lock().as_mut() {
+        Some(generator) => {
+            generator.random.fill(target);
+            true
+        }
+        None => false
+    }
+}
+
+/// Generates a 2048 bit RSA key from the seed.
+///
+/// Returns `None` if deterministic mode is not enabled.
+pub fn rsa_key() -> Option<Result<Rsa<Private>, ErrorStack>> {
+    lock().as_mut().map(|generator| generate_rsa(&mut generator.keys))
+}
+
+/// Generates an ECDSA key for the given curve from the seed.
+///
+/// Returns `None` if deterministic mode is not enabled.
+pub fn ec_key(
+    group: &EcGroupRef
+) -> Option<Result<EcKey<Private>, ErrorStack>> {
+    lock().as_mut().map(|generator| generate_ec(&mut generator.keys, group))
+}
+
+/// Returns the DER-encoded private key for a one-off RSA key.
+///
+/// Returns `None` if deterministic mode is not enabled.
+pub fn one_off_key() -> Result<Option<Vec<u8>>, io::Error> {
+    let key = lock().as_mut().map(|generator| {
+        generate_rsa(&mut generator.one_off)
+    });
+    match key {
+        Some(Ok(key)) => Ok(Some(key.private_key_to_der()?)),
+        Some(Err(err)) => Err(err.into()),
+        None => Ok(None)
+    }
+}
+
+/// Locks the generator.
+fn lock() -> std::sync::MutexGuard<'static, Option<Generator>> {
+    GENERATOR.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+
+//------------ Generator -----------------------------------------------------
+
+/// The generators for the different purposes.
+struct Generator {
+    /// The generator for long-lived keys.
+    keys: SeededRng,
+
+    /// The generator for one-off keys of signed objects.
+    one_off: SeededRng,
+
+    /// The generator for all other random values.
+    random: SeededRng,
+}
+
+impl Generator {
+    /// Creates the generators from a seed and invocation label.
+    fn new(seed: u64, label: &str) -> Self {
+        Generator {
+            keys: SeededRng::new(seed, label, "keys"),
+            one_off: SeededRng::new(seed, label, "one-off"),
+            random: SeededRng::new(seed, label, "random"),
+        }
+    }
+}
+
+
+//------------ SeededRng -----------------------------------------------------
+
+/// A random number generator producing the same output for the same seed.
+struct SeededRng {
+    /// The key derived from seed, label, and purpose.
+    key: [u8; 32],
+
+    /// The number of the next block.
+    counter: u64,
+
+    /// The current block.
+    block: [u8; 32],
+
+    /// The number of octets of `block` already used.
+    used: usize,
+}
+
+impl SeededRng {
+    /// Creates a new generator from a seed, label, and purpose.
+    fn new(seed: u64, label: &str, purpose: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(&seed.to_be_bytes());
+        hasher.update(&(label.len() as u64).to_be_bytes());
+        hasher.update(label.as_bytes());
+        hasher.update(purpose.as_bytes());
+        SeededRng {
+            key: hasher.finish(), counter: 0, block: [0; 32], used: 32
+        }
+    }
+
+    /// Fills `target` with the next octets.
+    fn fill(&mut self, target: &mut [u8]) {
+        for item in target {
+            if self.used == self.block.len() {
+                let mut hasher = Sha256::new();
+                hasher.update(&self.key);
+                hasher.update(&self.counter.to_be_bytes());
+                self.block = hasher.finish();
+                self.counter += 1;
+                self.used = 0;
+            }
+            *item = self.block[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+
+//------------ Key Generation ------------------------------------------------
+
+/// The number of octets of each of the two primes of an RSA key.
+const RSA_PRIME_LEN: usize = 128;
+
+/// The public exponent of RSA keys.
+const RSA_EXPONENT: u32 = 65537;
+
+/// Generates an RSA key from the generator.
+fn generate_rsa(rng: &mut SeededRng) -> Result<Rsa<Private>, ErrorStack> {
+    let mut ctx = BigNumContext::new()?;
+    let e = BigNum::from_u32(RSA_EXPONENT)?;
+    let (p, q) = loop {
+        let p = generate_prime(rng, &mut ctx)?;
+        let q = generate_prime(rng, &mut ctx)?;
+        if p > q {
+            break (p, q)
+        }
+        if q > p {
+            break (q, p)
+        }
+    };
+    let mut p1 = p.to_owned()?;
+    p1.sub_word(1)?;
+    let mut q1 = q.to_owned()?;
+    q1.sub_word(1)?;
+    let mut phi = BigNum::new()?;
+    phi.checked_mul(&p1, &q1, &mut ctx)?;
+    let mut n = BigNum::new()?;
+    n.checked_mul(&p, &q, &mut ctx)?;
+    let mut d = BigNum::new()?;
+    d.mod_inverse(&e, &phi, &mut ctx)?;
+    let mut dmp1 = BigNum::new()?;
+    dmp1.checked_rem(&d, &p1, &mut ctx)?;
+    let mut dmq1 = BigNum::new()?;
+    dmq1.checked_rem(&d, &q1, &mut ctx)?;
+    let mut iqmp = BigNum::new()?;
+    iqmp.mod_inverse(&q, &p, &mut ctx)?;
+    Rsa::from_private_components(n, e, d, p, q, dmp1, dmq1, iqmp)
+}
+
+/// Generates a prime suitable for an RSA key from the generator.
+///
+/// The two most significant bits are set so that the product of two such
+/// primes has exactly twice their bit length. Primes for which the public
+/// exponent isn’t invertible are skipped.
+fn generate_prime(
+    rng: &mut SeededRng, ctx: &mut BigNumContext
+) -> Result<BigNum, ErrorStack> {
+    let mut buf = [0u8; RSA_PRIME_LEN];
+    loop {
+        rng.fill(&mut buf);
+        buf[0] |= 0xC0;
+        buf[RSA_PRIME_LEN - 1] |= 0x01;
+        let candidate = BigNum::from_slice(&buf)?;
+        if candidate.mod_word(RSA_EXPONENT)? == 1 {
+            continue
+        }
+        if candidate.is_prime(64, ctx)? {
+            return Ok(candidate)
+        }
+    }
+}
+
+/// Generates an ECDSA key from the generator.
+fn generate_ec(
+    rng: &mut SeededRng, group: &EcGroupRef
+) -> Result<EcKey<Private>, ErrorStack> {
+    let mut ctx = BigNumContext::new()?;
+    let mut order = BigNum::new()?;
+    group.order(&mut order, &mut ctx)?;
+    let mut buf = vec![0u8; order.num_bytes() as usize + 8];
+    rng.fill(&mut buf);
+    let value = BigNum::from_slice(&buf)?;
+    order.sub_word(1)?;
+    let mut private = BigNum::new()?;
+    private.nnmod(&value, &order, &mut ctx)?;
+    private.add_word(1)?;
+    let mut public = EcPoint::new(group)?;
+    public.mul_generator2(group, &private, &mut ctx)?;
+    EcKey::from_private_components(group, &private, &public)
+}
@@ -0,0 +1,157 @@
+//! The signer for the keys given on the command line.
+//!
+//! Keys are either private key files handled by the OpenSSL soft signer or
+//! keys in a PKCS#11 token. Since the commands don’t care which one it is,
+//! `KeySigner` wraps both and dispatches to the right one.
+//!
+//! In deterministic mode, one-off keys and random data come from the seeded
+//! generator instead of the underlying signer.
+
+use std::io;
+use std::os::raw::c_ulong;
+use rpki::crypto::{
+    PublicKey, PublicKeyFormat, Signature, SignatureAlgorithm, Signer,
+    SigningError
+};
+use rpki::crypto::signer::KeyError;
+use rpki::crypto::softsigner::{KeyId, OpenSslSigner};
+use crate::pkcs11::Pkcs11Signer;
+use crate::seeded;
+
+
+//------------ KeySigner -----------------------------------------------------
+
+/// A signer for either a key file or a PKCS#11 key.
+pub enum KeySigner {
+    /// A signer for private key files.
+    OpenSsl(OpenSslSigner),
+
+    /// A signer for keys in a PKCS#11 token.
+    Pkcs11(Pkcs11Signer),
+}
+
+impl Signer for KeySigner {
+    type KeyId = KeyRef;
+    type Error = io::Error;
+
+    fn create_key(
+        &self, algorithm: PublicKeyFormat
+    ) -> Result<Self::KeyId, Self::Error> {
+        match self {
+            KeySigner::OpenSsl(signer) => {
+                signer.create_key(algorithm).map(KeyRef::OpenSsl)
+            }
+            KeySigner::Pkcs11(signer) => {
+                signer.create_key(algorithm).map(KeyRef::Pkcs11)
+            }
+        }
+    }
+
+    fn get_key_info(
+        &self, key: &Self::KeyId
+    ) -> Result<PublicKey, KeyError<Self::Error>> {
+        match (self, key) {
+            (KeySigner::OpenSsl(signer), KeyRef::OpenSsl(key)) => {
+                signer.get_key_info(key)
+            }
+            (KeySigner::Pkcs11(signer), KeyRef::Pkcs11(key)) => {
+                signer.get_key_info(key)
+            }
+            _ => Err(KeyError::KeyNotFound)
+        }
+    }
+
+    fn destroy_key(
+        &self, key: &Self::KeyId
+    ) -> Result<(), KeyError<Self::Error>> {
+        match (self, key) {
+            (KeySigner::OpenSsl(signer), KeyRef::OpenSsl(key)) => {
+                signer.destroy_key(key)
+            }
+            (KeySigner::Pkcs11(signer), KeyRef::Pkcs11(key)) => {
+                signer.destroy_key(key)
+            }
+            _ => Err(KeyError::KeyNotFound)
+        }
+    }
+
+    fn sign<Alg: SignatureAlgorithm, D: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Self::KeyId,
+        algorithm: Alg,
+        data: &D
+    ) -> Result<Signature<Alg>, SigningError<Self::Error>> {
+        match (self, key) {
+            (KeySigner::OpenSsl(signer), KeyRef::OpenSsl(key)) => {
+                signer.sign(key, algorithm, data)
+            }
+            (KeySigner::Pkcs11(signer), KeyRef::Pkcs11(key)) => {
+                signer.sign(key, algorithm, data)
+            }
+            _ => Err(SigningError::KeyNotFound)
+        }
+    }
+
+    fn sign_one_off<Alg: SignatureAlgorithm, D: AsRef<[u8]> + ?Sized>(
+        &self,
+        algorithm: Alg,
+        data: &D
+    ) -> Result<(Signature<Alg>, PublicKey), Self::Error> {
+        if algorithm.public_key_format() == PublicKeyFormat::Rsa {
+            if let Some(der) = seeded::one_off_key()? {
+                return sign_with_der(&der, algorithm, data)
+            }
+        }
+        match self {
+            KeySigner::OpenSsl(signer) => signer.sign_one_off(algorithm, data),
+            KeySigner::Pkcs11(signer) => signer.sign_one_off(algorithm, data),
+        }
+    }
+
+    fn rand(&self, target: &mut [u8]) -> Result<(), Self::Error> {
+        if seeded::fill(target) {
+            return Ok(())
+        }
+        match self {
+            KeySigner::OpenSsl(signer) => signer.rand(target),
+            KeySigner::Pkcs11(signer) => signer.rand(target),
+        }
+    }
+}
+
+
+/// Signs data with a one-off key given as a DER-encoded private key.
+fn sign_with_der<Alg: SignatureAlgorithm, D: AsRef<[u8]> + ?Sized>(
+    der: &[u8],
+    algorithm: Alg,
+    data: &D
+) -> Result<(Signature<Alg>, PublicKey), io::Error> {
+    let signer = OpenSslSigner::new();
+    let key = signer.key_from_der(der)?;
+    let signature = signer.sign(&key, algorithm, data).map_err(|err| {
+        match err {
+            SigningError::Signer(err) => err,
+            err => io::Error::other(err.to_string()),
+        }
+    })?;
+    let public = signer.get_key_info(&key).map_err(|err| {
+        match err {
+            KeyError::Signer(err) => err,
+            err => io::Error::other(format!("{:?}", err)),
+        }
+    })?;
+    Ok((signature, public))
+}
+
+
+//------------ KeyRef --------------------------------------------------------
+
+/// The identifier of a key of a `KeySigner`.
+#[derive(Clone, Copy, Debug)]
+pub enum KeyRef {
+    /// The key ID within the OpenSSL signer.
+    OpenSsl(KeyId),
+
+    /// The object handle of the private key in the PKCS#11 token.
+    Pkcs11(c_ulong),
+}
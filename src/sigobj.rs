@@ -0,0 +1,222 @@
+//! Creating signed objects.
+//!
+//! The `SignedObjectBuilder` of the rpki crate always includes the signed
+//! object URI in the subject information access of the EE certificate.
+//! Some objects, such as RPKI Signed Checklists, must not have one, so this
+//! module provides a builder that leaves it optional. It also allows
+//! overriding the authority key identifier of the EE certificate for
+//! creating deliberately broken objects.
+
+use bcder::{Captured, Mode, OctetString, Oid, Tag};
+use bcder::encode;
+use bcder::encode::{PrimitiveContent, Values};
+use rpki::crypto::{
+    DigestAlgorithm, KeyIdentifier, RpkiSignatureAlgorithm, Signer,
+    SigningError
+};
+use rpki::oid;
+use rpki::repository::cert::{KeyUsage, Overclaim, TbsCert};
+use rpki::repository::resources::{AsResources, IpResources};
+use rpki::repository::x509::{Serial, Validity};
+use rpki::uri;
+
+
+//------------ SignedObjectBuilder -------------------------------------------
+
+/// A builder for signed objects with an optional signed object URI.
+#[derive(Clone, Debug)]
+pub struct SignedObjectBuilder {
+    /// The serial number of the EE certificate.
+    serial_number: Serial,
+
+    /// The validity of the EE certificate.
+    validity: Validity,
+
+    /// The URI of CRL for the EE certificate.
+    crl_uri: uri::Rsync,
+
+    /// The URI of the CA certificate issuing the EE certificate.
+    ca_issuer: uri::Rsync,
+
+    /// The URI of the signed object itself.
+    ///
+    /// If this is `None`, the EE certificate will not have a subject
+    /// information access extension.
+    signed_object: Option<uri::Rsync>,
+
+    /// The IPv4 resources of the EE certificate.
+    v4_resources: IpResources,
+
+    /// The IPv6 resources of the EE certificate.
+    v6_resources: IpResources,
+
+    /// The AS resources of the EE certificate.
+    as_resources: AsResources,
+
+    /// The authority key identifier of the EE certificate.
+    ///
+    /// If this is `None`, the key identifier of the issuer’s key is used.
+    authority_key_identifier: Option<KeyIdentifier>,
+}
+
+impl SignedObjectBuilder {
+    pub fn new(
+        serial_number: Serial,
+        validity: Validity,
+        crl_uri: uri::Rsync,
+        ca_issuer: uri::Rsync,
+        signed_object: Option<uri::Rsync>,
+    ) -> Self {
+        SignedObjectBuilder {
+            serial_number,
+            validity,
+            crl_uri,
+            ca_issuer,
+            signed_object,
+            v4_resources: IpResources::missing(),
+            v6_resources: IpResources::missing(),
+            as_resources: AsResources::missing(),
+            authority_key_identifier: None,
+        }
+    }
+
+    /// Returns the IPv4 resources of the EE certificate.
+    pub fn v4_resources(&self) -> &IpResources {
+        &self.v4_resources
+    }
+
+    /// Sets the IPv4 resources of the EE certificate.
+    pub fn set_v4_resources(&mut self, resources: IpResources) {
+        self.v4_resources = resources
+    }
+
+    /// Returns the IPv6 resources of the EE certificate.
+    pub fn v6_resources(&self) -> &IpResources {
+        &self.v6_resources
+    }
+
+    /// Sets the IPv6 resources of the EE certificate.
+    pub fn set_v6_resources(&mut self, resources: IpResources) {
+        self.v6_resources = resources
+    }
+
+    /// Returns the AS resources of the EE certificate.
+    pub fn as_resources(&self) -> &AsResources {
+        &self.as_resources
+    }
+
+    /// Sets the AS resources of the EE certificate.
+    pub fn set_as_resources(&mut self, resources: AsResources) {
+        self.as_resources = resources
+    }
+
+    /// Overrides the authority key identifier of the EE certificate.
+    pub fn set_authority_key_identifier(&mut self, key_id: KeyIdentifier) {
+        self.authority_key_identifier = Some(key_id)
+    }
+
+    /// Signs the content and returns the encoded signed object.
+    pub fn finalize<S: Signer>(
+        self,
+        content_type: Oid<&[u8]>,
+        content: &[u8],
+        signer: &S,
+        issuer_key: &S::KeyId,
+    ) -> Result<Captured, SigningError<S::Error>> {
+        let issuer_pub = signer.get_key_info(issuer_key)?;
+        let digest_algorithm = DigestAlgorithm::default();
+
+        // Produce and sign the signed attributes with a one-off key.
+        let signed_attrs = signed_attrs(
+            &content_type, digest_algorithm.digest(content).as_ref()
+        );
+        let (signature, key_info) = signer.sign_one_off(
+            RpkiSignatureAlgorithm::default(),
+            &encode::set(&signed_attrs).to_captured(Mode::Der)
+        )?;
+        let sid = key_info.key_identifier();
+
+        // Make the EE certificate.
+        let mut cert = TbsCert::new(
+            self.serial_number,
+            issuer_pub.to_subject_name(),
+            self.validity,
+            None,
+            key_info,
+            KeyUsage::Ee,
+            Overclaim::Refuse,
+        );
+        cert.set_authority_key_identifier(Some(
+            self.authority_key_identifier.unwrap_or_else(|| {
+                issuer_pub.key_identifier()
+            })
+        ));
+        cert.set_crl_uri(Some(self.crl_uri));
+        cert.set_ca_issuer(Some(self.ca_issuer));
+        cert.set_signed_object(self.signed_object);
+        cert.set_v4_resources(self.v4_resources);
+        cert.set_v6_resources(self.v6_resources);
+        cert.set_as_resources(self.as_resources);
+        let cert = cert.into_cert(signer, issuer_key)?;
+
+        let res = encode::sequence((
+            oid::SIGNED_DATA.encode(), // contentType
+            encode::sequence_as(Tag::CTX_0, // content
+                encode::sequence((
+                    3u8.encode(), // version
+                    digest_algorithm.encode_set(), // digestAlgorithms
+                    encode::sequence(( // encapContentInfo
+                        content_type.encode_ref(),
+                        encode::sequence_as(Tag::CTX_0,
+                            OctetString::encode_slice(content)
+                        ),
+                    )),
+                    encode::sequence_as(Tag::CTX_0, // certificates
+                        cert.encode_ref(),
+                    ),
+                    encode::set( // signerInfo
+                        encode::sequence(( // SignerInfo
+                            3u8.encode(), // version
+                            sid.encode_ref_as(Tag::CTX_0),
+                            digest_algorithm.encode(), // digestAlgorithm
+                            encode::sequence_as(Tag::CTX_0, // signedAttrs
+                                &signed_attrs
+                            ),
+                            signature.algorithm().cms_encode(),
+                            OctetString::encode_slice( // signature
+                                signature.value().as_ref()
+                            ),
+                        ))
+                    )
+                ))
+            )
+        )).to_captured(Mode::Der);
+        Ok(res)
+    }
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Creates the content of the signed attributes.
+///
+/// In DER, the members of a SET OF are ordered by their encoding, so we
+/// encode each attribute and sort them.
+fn signed_attrs(content_type: &Oid<&[u8]>, digest: &[u8]) -> Captured {
+    let mut attrs = vec![
+        encode::sequence((
+            oid::CONTENT_TYPE.encode(),
+            encode::set(content_type.encode_ref()),
+        )).to_captured(Mode::Der),
+        encode::sequence((
+            oid::MESSAGE_DIGEST.encode(),
+            encode::set(OctetString::encode_slice(digest)),
+        )).to_captured(Mode::Der),
+    ];
+    attrs.sort_by(|left, right| left.as_slice().cmp(right.as_slice()));
+    let mut res = Captured::builder(Mode::Der);
+    for attr in attrs {
+        res.extend(attr)
+    }
+    res.freeze()
+}
@@ -0,0 +1,416 @@
+//! Persistent state of a CA.
+//!
+//! Instead of giving serial numbers, CRL numbers, and manifest numbers
+//! explicitly, the commands can keep them in a state directory for the
+//! issuing CA. The state also records all issued certificates and all
+//! revoked serial numbers so that a new CRL can include all of them.
+//!
+//! The state is kept in the file `state.toml` in the state directory.
+//!
+//! When the key of the CA is rolled via the `rollover` command, the state
+//! also keeps the current key and, while the roll is in progress, the new
+//! key together with their certificates and publication points.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use chrono::SecondsFormat;
+use rpki::repository::crl::CrlEntry;
+use rpki::repository::x509::{Serial, Time};
+use mkrpki::Error;
+use serde::{Deserialize, Serialize};
+use super::{load_file, save_file};
+
+
+//------------ CaState -------------------------------------------------------
+
+/// The persistent state of a CA.
+pub struct CaState {
+    /// The path of the state file.
+    path: PathBuf,
+
+    /// The content of the state file.
+    content: StateContent,
+}
+
+impl CaState {
+    /// Loads the state from a state directory.
+    ///
+    /// If the directory or the state file do not exist yet, a new state is
+    /// started.
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join("state.toml");
+        let content = if path.exists() {
+            let data = load_file(&path)?;
+            match toml::from_slice(&data) {
+                Ok(content) => content,
+                Err(err) => {
+                    return Err(Error::Encoding(format!(
+                        "Invalid CA state {}: {}", path.display(), err
+                    )))
+                }
+            }
+        }
+        else {
+            if let Err(err) = fs::create_dir_all(dir) {
+                return Err(Error::Io(format!(
+                    "Failed to create directory {}: {}", dir.display(), err
+                )))
+            }
+            StateContent::default()
+        };
+        Ok(CaState { path, content })
+    }
+
+    /// Loads the state if a state directory was given.
+    pub fn load_opt(dir: Option<&Path>) -> Result<Option<Self>, Error> {
+        dir.map(Self::load).transpose()
+    }
+
+    /// Writes the state back to the state file.
+    pub fn save(&self) -> Result<(), Error> {
+        let data = match toml::to_string(&self.content) {
+            Ok(data) => data,
+            Err(err) => {
+                return Err(Error::Encoding(format!(
+                    "Failed to encode CA state {}: {}",
+                    self.path.display(), err
+                )))
+            }
+        };
+        save_file(&self.path, data.as_bytes())
+    }
+
+    /// Returns the value to use for a counter.
+    ///
+    /// If `explicit` is given, it is used and the counter is moved past it
+    /// if necessary. Otherwise the next value of the counter is used.
+    fn take(&mut self, counter: Counter, explicit: Option<Serial>) -> Serial {
+        let next = match counter {
+            Counter::Serial => &mut self.content.next_serial,
+            Counter::CrlNumber => &mut self.content.next_crl_number,
+            Counter::ManifestNumber => &mut self.content.next_manifest_number,
+        };
+        match explicit {
+            Some(value) => {
+                if let Ok(value) = u64::from_str(&value.to_string()) {
+                    if value >= *next {
+                        *next = value.saturating_add(1);
+                    }
+                }
+                value
+            }
+            None => {
+                let res = *next;
+                *next += 1;
+                res.into()
+            }
+        }
+    }
+
+    /// Returns the current key of the CA if it is known.
+    pub fn key(&self) -> Option<&CaKey> {
+        self.content.key.as_ref()
+    }
+
+    /// Returns the key roll in progress, if any.
+    pub fn key_roll(&self) -> Option<&KeyRoll> {
+        self.content.key_roll.as_ref()
+    }
+
+    /// Starts a key roll from the current key to a new key.
+    pub fn stage_key(&mut self, current: CaKey, new: CaKey) {
+        self.content.key = Some(current);
+        self.content.key_roll = Some(KeyRoll {
+            stage: RollStage::Staged,
+            new,
+        })
+    }
+
+    /// Activates the new key of the key roll in progress.
+    ///
+    /// All issued and revoked certificates so far belong to the old key, so
+    /// they are forgotten.
+    pub fn activate_key(&mut self) {
+        if let Some(ref mut roll) = self.content.key_roll {
+            roll.stage = RollStage::Active;
+            self.content.issued.clear();
+            self.content.revoked.clear();
+        }
+    }
+
+    /// Finishes the key roll in progress making the new key the current key.
+    pub fn retire_key(&mut self) {
+        if let Some(roll) = self.content.key_roll.take() {
+            self.content.key = Some(roll.new);
+        }
+    }
+
+    /// Records an issued certificate.
+    pub fn issue(
+        &mut self, serial: Serial, kind: &str, file: &Path, not_after: Time
+    ) {
+        self.content.issued.push(IssuedCert {
+            serial: serial.to_string(),
+            kind: kind.into(),
+            file: file.display().to_string(),
+            not_after: format_time(not_after),
+        })
+    }
+
+    /// Records the revocation of a certificate.
+    ///
+    /// If the certificate has been revoked before, the earlier revocation
+    /// is kept.
+    pub fn revoke(&mut self, serial: Serial, revocation_date: Time) {
+        let serial = serial.to_string();
+        if self.content.revoked.iter().any(|item| item.serial == serial) {
+            return
+        }
+        if !self.content.issued.iter().any(|item| item.serial == serial) {
+            eprintln!(
+                "Warning: certificate {} was not issued via this state.",
+                serial
+            );
+        }
+        self.content.revoked.push(RevokedCert {
+            serial,
+            revocation_date: format_time(revocation_date),
+        })
+    }
+
+    /// Returns the CRL entries for all revoked certificates.
+    pub fn crl_entries(&self) -> Result<Vec<CrlEntry>, Error> {
+        self.content.revoked.iter().map(|item| {
+            let serial = Serial::from_str(&item.serial).map_err(|_| {
+                Error::Encoding(format!(
+                    "Invalid serial number '{}' in CA state {}.",
+                    item.serial, self.path.display()
+                ))
+            })?;
+            let date = Time::from_str(&item.revocation_date).map_err(|_| {
+                Error::Encoding(format!(
+                    "Invalid revocation date '{}' in CA state {}.",
+                    item.revocation_date, self.path.display()
+                ))
+            })?;
+            Ok(CrlEntry::new(serial, date))
+        }).collect()
+    }
+}
+
+
+//------------ Counter -------------------------------------------------------
+
+/// The counters kept in the CA state.
+#[derive(Clone, Copy, Debug)]
+pub enum Counter {
+    /// The serial number of issued certificates.
+    Serial,
+
+    /// The CRL number.
+    CrlNumber,
+
+    /// The manifest number.
+    ManifestNumber,
+}
+
+impl Counter {
+    /// Returns the command line option for giving the value explicitly.
+    fn option(self) -> &'static str {
+        match self {
+            Counter::Serial => "--serial",
+            Counter::CrlNumber => "--crl",
+            Counter::ManifestNumber => "--number",
+        }
+    }
+}
+
+/// Returns the value to use for a counter.
+///
+/// Uses the explicitly given value if present or the next value from the
+/// state. Fails if neither is available.
+pub fn take_counter(
+    state: Option<&mut CaState>, counter: Counter, explicit: Option<Serial>
+) -> Result<Serial, Error> {
+    match (state, explicit) {
+        (Some(state), explicit) => Ok(state.take(counter, explicit)),
+        (None, Some(value)) => Ok(value),
+        (None, None) => {
+            Err(Error::Usage(format!(
+                "Either {} or --state must be given.", counter.option()
+            )))
+        }
+    }
+}
+
+
+//------------ StateContent --------------------------------------------------
+
+/// The content of the state file.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct StateContent {
+    /// The next serial number for issued certificates.
+    next_serial: u64,
+
+    /// The next CRL number.
+    next_crl_number: u64,
+
+    /// The next manifest number.
+    next_manifest_number: u64,
+
+    /// All certificates issued so far.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    issued: Vec<IssuedCert>,
+
+    /// All certificates revoked so far.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    revoked: Vec<RevokedCert>,
+
+    /// The current key of the CA.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<CaKey>,
+
+    /// The key roll in progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_roll: Option<KeyRoll>,
+}
+
+impl Default for StateContent {
+    fn default() -> Self {
+        StateContent {
+            next_serial: 1,
+            next_crl_number: 1,
+            next_manifest_number: 1,
+            issued: Vec::new(),
+            revoked: Vec::new(),
+            key: None,
+            key_roll: None,
+        }
+    }
+}
+
+
+//------------ IssuedCert ----------------------------------------------------
+
+/// A certificate issued by the CA.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct IssuedCert {
+    /// The serial number of the certificate.
+    serial: String,
+
+    /// The kind of object, i.e., the command used to create it.
+    kind: String,
+
+    /// The file the object was written to.
+    file: String,
+
+    /// The not-after time of the certificate.
+    not_after: String,
+}
+
+
+//------------ RevokedCert ---------------------------------------------------
+
+/// A certificate revoked by the CA.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RevokedCert {
+    /// The serial number of the certificate.
+    serial: String,
+
+    /// The time of revocation.
+    revocation_date: String,
+}
+
+
+//------------ CaKey ---------------------------------------------------------
+
+/// A key of the CA with its certificate and publication point.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CaKey {
+    /// The file with the private key.
+    key: String,
+
+    /// The file with the CA certificate for the key.
+    cert: String,
+
+    /// The directory with the objects published under the key.
+    repository: String,
+}
+
+impl CaKey {
+    pub fn new(key: &Path, cert: &Path, repository: &Path) -> Self {
+        CaKey {
+            key: key.display().to_string(),
+            cert: cert.display().to_string(),
+            repository: repository.display().to_string(),
+        }
+    }
+
+    /// Returns the path of the private key file.
+    pub fn key(&self) -> &Path {
+        Path::new(&self.key)
+    }
+
+    /// Returns the path of the CA certificate.
+    pub fn cert(&self) -> &Path {
+        Path::new(&self.cert)
+    }
+
+    /// Returns the path of the publication point directory.
+    pub fn repository(&self) -> &Path {
+        Path::new(&self.repository)
+    }
+}
+
+
+//------------ KeyRoll -------------------------------------------------------
+
+/// A key roll in progress.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct KeyRoll {
+    /// How far the key roll has progressed.
+    stage: RollStage,
+
+    /// The new key.
+    new: CaKey,
+}
+
+impl KeyRoll {
+    /// Returns how far the key roll has progressed.
+    pub fn stage(&self) -> RollStage {
+        self.stage
+    }
+
+    /// Returns the new key.
+    pub fn new_key(&self) -> &CaKey {
+        &self.new
+    }
+}
+
+
+//------------ RollStage -----------------------------------------------------
+
+/// How far a key roll has progressed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RollStage {
+    /// The new key has been published but isn’t used yet.
+    Staged,
+
+    /// All objects have been re-signed with the new key.
+    Active,
+}
+
+
+//------------ Helpers -------------------------------------------------------
+
+/// Formats a time for the state file.
+fn format_time(time: Time) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
@@ -0,0 +1,537 @@
+//! Validating a repository.
+//!
+//! Starting from a TAL, this walks a local copy of a repository in rsync
+//! layout, i.e., with the host name and module as the first two directory
+//! levels, and validates all objects found via the manifests of the CAs.
+//! The resulting VRPs and ASPA assertions are printed to stdout, all
+//! validation failures to stderr.
+//!
+//! Validity and staleness are checked against the reference time, so a
+//! repository created with `--now` can be validated as of the same time.
+
+use std::fmt;
+use std::fs;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use bytes::Bytes;
+use mkrpki::Error;
+use rpki::repository::aspa::Aspa;
+use rpki::repository::cert::{Cert, ResourceCert};
+use rpki::repository::crl::Crl;
+use rpki::repository::manifest::{Manifest, ManifestContent};
+use rpki::repository::roa::Roa;
+use rpki::repository::sigobj::SignedObject;
+use rpki::repository::tal::TalInfo;
+use rpki::repository::x509::Time;
+use rpki::uri;
+use structopt::StructOpt;
+use crate::clock;
+use super::{load_file, write_stdout, TalContent};
+
+
+//------------ Validate ------------------------------------------------------
+
+#[derive(StructOpt)]
+pub struct Validate {
+    /// Path to the TAL to start validation from.
+    #[structopt(long="tal")]
+    tal: PathBuf,
+
+    /// Path to the directory with the repository in rsync layout.
+    #[structopt(long="repository")]
+    repository: PathBuf,
+
+    /// Use strict validation.
+    #[structopt(long="strict")]
+    strict: bool,
+}
+
+impl Validate {
+    pub fn run(self) -> Result<(), Error> {
+        let tal = TalContent::load(&self.tal)?;
+        let name = self.tal.file_stem().and_then(OsStr::to_str).unwrap_or(
+            "tal"
+        ).to_string();
+        let mut validation = Validation {
+            repository: self.repository,
+            strict: self.strict,
+            now: clock::now(),
+            manifests: HashSet::new(),
+            vrps: Vec::new(),
+            aspas: Vec::new(),
+            objects: 0,
+            failures: 0,
+        };
+        validation.process_tal(&tal, name, &self.tal);
+
+        validation.vrps.sort();
+        validation.aspas.sort();
+        let mut output = String::new();
+        for vrp in &validation.vrps {
+            output.push_str(&format!("VRP  {}\n", vrp));
+        }
+        for aspa in &validation.aspas {
+            output.push_str(&format!("ASPA {}\n", aspa));
+        }
+        write_stdout(&output)?;
+        eprintln!(
+            "Validated {} objects: {} VRPs, {} ASPAs, {} failures.",
+            validation.objects, validation.vrps.len(),
+            validation.aspas.len(), validation.failures
+        );
+        if validation.failures > 0 {
+            Err(Error::Invalid("Validation failed.".into()))
+        }
+        else {
+            Ok(())
+        }
+    }
+}
+
+
+//------------ Validation ----------------------------------------------------
+
+/// The state of a validation run.
+struct Validation {
+    /// The directory with the repository.
+    repository: PathBuf,
+
+    /// Use strict validation?
+    strict: bool,
+
+    /// The time to validate at.
+    now: Time,
+
+    /// The URIs of all manifests processed so far.
+    ///
+    /// This is used to protect against loops in the CA tree.
+    manifests: HashSet<String>,
+
+    /// The VRPs found so far.
+    vrps: Vec<String>,
+
+    /// The ASPA assertions found so far.
+    aspas: Vec<String>,
+
+    /// The number of objects that have been validated successfully.
+    objects: usize,
+
+    /// The number of validation failures.
+    failures: usize,
+}
+
+impl Validation {
+    /// Processes a TAL and everything published under its trust anchor.
+    fn process_tal(&mut self, tal: &TalContent, name: String, path: &Path) {
+        let uri = match tal.uris.iter().find_map(|uri| {
+            uri::Rsync::from_str(uri).ok()
+        }) {
+            Some(uri) => uri,
+            None => return self.failure(path, "TAL without rsync URI")
+        };
+        let path = self.uri_path(&uri);
+        let data = match self.load(&path) {
+            Some(data) => data,
+            None => return
+        };
+        let cert = match Cert::decode(data) {
+            Ok(cert) => cert,
+            Err(err) => return self.failure(&path, err)
+        };
+        if cert.subject_public_key_info().key_identifier()
+            != tal.key.key_identifier()
+        {
+            return self.failure(&path, "key does not match the TAL")
+        }
+        let cert = match cert.validate_ta_at(
+            TalInfo::from_name(name).into_arc(), self.strict, self.now
+        ) {
+            Ok(cert) => cert,
+            Err(err) => return self.failure(&path, err)
+        };
+        self.objects += 1;
+        self.process_ca(&cert, &path);
+    }
+
+    /// Processes all objects published by a CA.
+    fn process_ca(&mut self, ca: &ResourceCert, ca_path: &Path) {
+        let (repository, mft_uri) = match (
+            ca.ca_repository(), ca.rpki_manifest()
+        ) {
+            (Some(repository), Some(mft)) => (repository, mft),
+            _ => {
+                return self.failure(
+                    ca_path, "CA certificate without repository URIs"
+                )
+            }
+        };
+        if !self.manifests.insert(mft_uri.to_string()) {
+            return self.failure(ca_path, "loop in CA tree")
+        }
+
+        let mft_path = self.uri_path(mft_uri);
+        let data = match self.load(&mft_path) {
+            Some(data) => data,
+            None => return
+        };
+        let mft = match Manifest::decode(data, self.strict) {
+            Ok(mft) => mft,
+            Err(err) => return self.failure(&mft_path, err)
+        };
+        let (mft_ee, mft) = match mft.validate_at(ca, self.strict, self.now) {
+            Ok(some) => some,
+            Err(err) => return self.failure(&mft_path, err)
+        };
+        if mft.next_update() < self.now {
+            self.failure(&mft_path, "manifest is stale");
+        }
+        self.objects += 1;
+
+        let (crl, crl_uri) = match self.process_crl(
+            ca, repository, &mft, &mft_path
+        ) {
+            Some(some) => some,
+            None => return
+        };
+        if crl.contains(mft_ee.serial_number()) {
+            self.failure(&mft_path, "manifest EE certificate is revoked");
+        }
+        self.check_crl_uri(&mft_path, &mft_ee, &crl_uri);
+
+        let mut listed = HashSet::new();
+        for item in mft.iter() {
+            let name = String::from_utf8_lossy(item.file()).into_owned();
+            if !listed.insert(name.clone()) {
+                self.failure(
+                    &mft_path,
+                    format_args!("file '{}' listed more than once", name)
+                );
+                continue
+            }
+            if name.ends_with(".crl") {
+                continue
+            }
+            let uri = match repository.join(name.as_bytes()) {
+                Ok(uri) => uri,
+                Err(_) => {
+                    self.failure(
+                        &mft_path,
+                        format_args!("invalid file name '{}'", name)
+                    );
+                    continue
+                }
+            };
+            let path = self.uri_path(&uri);
+            let data = match self.load_listed(&path, &mft, item.hash()) {
+                Some(data) => data,
+                None => continue
+            };
+            self.process_object(ca, &crl, &crl_uri, &path, data);
+        }
+        self.check_unlisted(repository, &mft, &[ca_path, &mft_path]);
+    }
+
+    /// Warns about files in a CA’s directory not listed on its manifest.
+    ///
+    /// The manifest itself and the CA certificate are not reported.
+    fn check_unlisted(
+        &mut self,
+        repository: &uri::Rsync,
+        mft: &ManifestContent,
+        skip: &[&Path],
+    ) {
+        let dir = self.uri_path(repository);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => return self.failure(&dir, err)
+        };
+        let mut unlisted = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() || skip.contains(&path.as_path()) {
+                continue
+            }
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !mft.iter().any(|item| {
+                item.file().as_ref() == name.as_bytes()
+            }) {
+                unlisted.push(path);
+            }
+        }
+        unlisted.sort();
+        for path in unlisted {
+            eprintln!(
+                "{}: Warning: not listed on manifest.", path.display()
+            );
+        }
+    }
+
+    /// Processes the CRL listed on a manifest.
+    ///
+    /// Returns the CRL and its URI.
+    fn process_crl(
+        &mut self,
+        ca: &ResourceCert,
+        repository: &uri::Rsync,
+        mft: &ManifestContent,
+        mft_path: &Path,
+    ) -> Option<(Crl, uri::Rsync)> {
+        let mut crls = mft.iter().filter(|item| {
+            item.file().ends_with(b".crl")
+        });
+        let item = match (crls.next(), crls.next()) {
+            (Some(item), None) => item,
+            (None, _) => {
+                self.failure(mft_path, "no CRL on manifest");
+                return None
+            }
+            (Some(_), Some(_)) => {
+                self.failure(mft_path, "more than one CRL on manifest");
+                return None
+            }
+        };
+        let uri = match repository.join(item.file()) {
+            Ok(uri) => uri,
+            Err(_) => {
+                self.failure(mft_path, "invalid CRL file name");
+                return None
+            }
+        };
+        let path = self.uri_path(&uri);
+        let data = self.load_listed(&path, mft, item.hash())?;
+        let mut crl = match Crl::decode(data) {
+            Ok(crl) => crl,
+            Err(err) => {
+                self.failure(&path, err);
+                return None
+            }
+        };
+        if let Err(err) = crl.verify_signature(ca.subject_public_key_info()) {
+            self.failure(&path, err);
+            return None
+        }
+        if *crl.as_cert_list().authority_key_identifier()
+            != ca.subject_public_key_info().key_identifier()
+        {
+            self.failure(&path, "CRL AKI does not match the CA key");
+            return None
+        }
+        if crl.as_cert_list().next_update() < self.now {
+            self.failure(&path, "CRL is stale");
+        }
+        crl.cache_serials();
+        self.objects += 1;
+        Some((crl, uri))
+    }
+
+    /// Checks that a certificate points to the CRL listed on the manifest.
+    ///
+    /// Reports a failure and returns `false` if it doesn’t.
+    fn check_crl_uri(
+        &mut self, path: &Path, cert: &Cert, crl_uri: &uri::Rsync
+    ) -> bool {
+        if cert.crl_uri() == Some(crl_uri) {
+            return true
+        }
+        self.failure(
+            path,
+            "CRL distribution point does not match the CRL on the manifest"
+        );
+        false
+    }
+
+    /// Processes a single object published by a CA.
+    fn process_object(
+        &mut self,
+        ca: &ResourceCert,
+        crl: &Crl,
+        crl_uri: &uri::Rsync,
+        path: &Path,
+        data: Bytes
+    ) {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("cer") => {
+                let cert = match Cert::decode(data) {
+                    Ok(cert) => cert,
+                    Err(err) => return self.failure(path, err)
+                };
+                if crl.contains(cert.serial_number()) {
+                    return self.failure(path, "certificate is revoked")
+                }
+                if !self.check_crl_uri(path, &cert, crl_uri) {
+                    return
+                }
+                if cert.extended_key_usage().is_some() {
+                    if let Err(err) = cert.validate_router_at(
+                        ca, self.strict, self.now
+                    ) {
+                        return self.failure(path, err)
+                    }
+                    self.objects += 1;
+                }
+                else {
+                    let cert = match cert.validate_ca_at(
+                        ca, self.strict, self.now
+                    ) {
+                        Ok(cert) => cert,
+                        Err(err) => return self.failure(path, err)
+                    };
+                    self.objects += 1;
+                    self.process_ca(&cert, path);
+                }
+            }
+            Some("roa") => {
+                let roa = match Roa::decode(data.clone(), self.strict) {
+                    Ok(roa) => roa,
+                    Err(err) => return self.failure(path, err)
+                };
+                let ee = match self.validate_signed(
+                    ca, crl, crl_uri, path, data
+                ) {
+                    Some(ee) => ee,
+                    None => return
+                };
+                let roa = roa.content();
+                let covered = roa.v4_addrs().iter().all(|addr| {
+                    ee.v4_resources().contains_roa(&addr)
+                }) && roa.v6_addrs().iter().all(|addr| {
+                    ee.v6_resources().contains_roa(&addr)
+                });
+                if !covered {
+                    return self.failure(
+                        path, "ROA prefix not covered by certificate"
+                    )
+                }
+                for addr in roa.iter() {
+                    self.vrps.push(format!(
+                        "{} {}/{}-{}",
+                        roa.as_id(), addr.address(), addr.address_length(),
+                        addr.max_length()
+                    ));
+                }
+                self.objects += 1;
+            }
+            Some("asa") => {
+                let aspa = match Aspa::decode(data.clone(), self.strict) {
+                    Ok(aspa) => aspa,
+                    Err(err) => return self.failure(path, err)
+                };
+                let ee = match self.validate_signed(
+                    ca, crl, crl_uri, path, data
+                ) {
+                    Some(ee) => ee,
+                    None => return
+                };
+                let aspa = aspa.content();
+                let covered = aspa.as_resources().to_blocks().map(|blocks| {
+                    ee.as_resources().contains(&blocks)
+                }).unwrap_or(false);
+                if !covered {
+                    return self.failure(
+                        path, "customer AS not covered by certificate"
+                    )
+                }
+                let mut line = aspa.customer_as().to_string();
+                for provider in aspa.provider_as_set().iter() {
+                    line.push_str(&format!(" {}", provider));
+                }
+                self.aspas.push(line);
+                self.objects += 1;
+            }
+            Some("gbr") | Some("sig") | Some("tak") => {
+                if self.validate_signed(ca, crl, crl_uri, path, data).is_some()
+                {
+                    self.objects += 1;
+                }
+            }
+            _ => {
+                eprintln!(
+                    "{}: Warning: unknown object type, skipped.",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    /// Validates a signed object and its EE certificate.
+    ///
+    /// Only the signed object itself is checked, not its content. Returns
+    /// the EE certificate or `None` if validation failed.
+    fn validate_signed(
+        &mut self,
+        ca: &ResourceCert,
+        crl: &Crl,
+        crl_uri: &uri::Rsync,
+        path: &Path,
+        data: Bytes
+    ) -> Option<ResourceCert> {
+        let obj = match SignedObject::decode(data, self.strict) {
+            Ok(obj) => obj,
+            Err(err) => {
+                self.failure(path, err);
+                return None
+            }
+        };
+        let ee = match obj.validate_at(ca, self.strict, self.now) {
+            Ok(ee) => ee,
+            Err(err) => {
+                self.failure(path, err);
+                return None
+            }
+        };
+        if crl.contains(ee.serial_number()) {
+            self.failure(path, "EE certificate is revoked");
+            return None
+        }
+        if !self.check_crl_uri(path, &ee, crl_uri) {
+            return None
+        }
+        Some(ee)
+    }
+
+    /// Loads a file listed on a manifest and checks its hash.
+    fn load_listed(
+        &mut self, path: &Path, mft: &ManifestContent, hash: &Bytes
+    ) -> Option<Bytes> {
+        let data = self.load(path)?;
+        if mft.file_hash_alg().digest(&data).as_ref() != hash.as_ref() {
+            self.failure(path, "hash does not match manifest");
+            return None
+        }
+        Some(data)
+    }
+
+    /// Loads a file, reporting a failure if that doesn’t work.
+    fn load(&mut self, path: &Path) -> Option<Bytes> {
+        if !path.exists() {
+            self.failure(path, "file is missing");
+            return None
+        }
+        match load_file(path) {
+            Ok(data) => Some(data.into()),
+            Err(err) => {
+                eprintln!("{}", err);
+                self.failures += 1;
+                None
+            }
+        }
+    }
+
+    /// Returns the path for an rsync URI.
+    fn uri_path(&self, uri: &uri::Rsync) -> PathBuf {
+        let mut res = self.repository.clone();
+        res.push(uri.canonical_authority().as_ref());
+        res.push(uri.module_name());
+        res.push(uri.path());
+        res
+    }
+
+    /// Reports a validation failure.
+    fn failure(&mut self, path: &Path, err: impl fmt::Display) {
+        eprintln!("{}: {}", path.display(), err);
+        self.failures += 1;
+    }
+}